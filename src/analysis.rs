@@ -0,0 +1,380 @@
+use crate::eigen;
+use crate::json;
+use crate::mesh::{Creases, Mesh};
+use crate::scheme::Scheme;
+use crate::stencil::{self, Sharp};
+
+use std::f64::consts::PI;
+use std::fmt;
+
+/// The rules are stored in single precision, so eigenvalues closer than this
+/// are taken to be the same one.
+const TOLERANCE: f64 = 1e-5;
+
+/// The eigen-analysis of the subdivision matrix of one vertex: the matrix
+/// that takes the vertex and its one-ring to the same vertices one level
+/// later. The ring is numbered from the centre, then for Loop the `n`
+/// neighbours, and for Catmull-Clark the `n` edge neighbours followed by the
+/// `n` vertices across each face.
+pub struct Analysis {
+    pub scheme: Scheme,
+    pub valence: usize,
+    /// Spokes, edges from the centre to its `i`th edge neighbour, kept sharp.
+    pub sharp: Vec<usize>,
+    pub matrix: Vec<Vec<f64>>,
+    /// From the largest magnitude down, as (real, imaginary) pairs.
+    pub eigenvalues: Vec<(f64, f64)>,
+    /// The eigenvalue after the dominant one, and how many times it appears
+    /// among the eigenvalues and among independent eigenvectors.
+    pub subdominant: (f64, f64),
+    pub multiplicity: usize,
+    pub eigenvectors: usize,
+    /// The one-ring placed at the subdominant eigenvectors, which is the
+    /// control net of the characteristic map. `None` unless the subdominant
+    /// eigenvalue is real with two eigenvectors.
+    pub characteristic_map: Option<Vec<[f64; 2]>>,
+    pub conditions: Vec<(&'static str, bool)>,
+}
+
+impl Analysis {
+    /// Builds the subdivision matrix from the same rules the stencils use,
+    /// with `sharp` spokes and their ends treated as crease edges and
+    /// corners.
+    pub fn new(scheme: Scheme, valence: usize, sharp: &[usize]) -> Analysis {
+        let n = valence;
+        let mut sharp = sharp.to_vec();
+        sharp.sort_unstable();
+        sharp.dedup();
+
+        let (positions, faces) = one_ring(scheme, n);
+        let mut creases = Creases::default();
+        for &i in &sharp {
+            creases.edges.push([0, 1 + i]);
+            creases.corners.extend([0, 1 + i]);
+        }
+        creases.corners.sort_unstable();
+        creases.corners.dedup();
+        let (rules, children) =
+            stencil::step(scheme, &positions, &faces, Some(&Sharp::marked(&creases)));
+
+        // the first child of the face after spoke i starts at the centre,
+        // then has the new vertex on the spoke and, for quads, the face point
+        let mut ring = vec![0];
+        ring.extend((0..n).map(|i| children[4 * i][1]));
+        if scheme == Scheme::CatmullClark {
+            ring.extend((0..n).map(|i| children[4 * i][2]));
+        }
+        let matrix: Vec<Vec<f64>> = ring
+            .iter()
+            .map(|&v| {
+                let mut row = vec![0.0; positions.len()];
+                for &(j, w) in &rules[v] {
+                    row[j] += w;
+                }
+                row
+            })
+            .collect();
+
+        let mut eigenvalues = eigen::eigenvalues(&matrix);
+        eigenvalues.sort_by(|a, b| {
+            magnitude(*b)
+                .total_cmp(&magnitude(*a))
+                .then(b.0.total_cmp(&a.0))
+                .then(a.1.total_cmp(&b.1))
+        });
+
+        let subdominant = eigenvalues[1];
+        let multiplicity = eigenvalues
+            .iter()
+            .filter(|&&e| magnitude((e.0 - subdominant.0, e.1 - subdominant.1)) < TOLERANCE)
+            .count();
+        let vectors = if subdominant.1 == 0.0 {
+            eigen::eigenvectors(&matrix, subdominant.0, TOLERANCE)
+        } else {
+            Vec::new()
+        };
+        let characteristic_map = match &vectors[..] {
+            [u, v] => Some(normalize(scheme, n, u, v)),
+            _ => None,
+        };
+
+        let dominant = eigenvalues[0];
+        let rest = eigenvalues.get(3).map_or(0.0, |&e| magnitude(e));
+        let conditions = vec![
+            (
+                "dominant eigenvalue is 1 and simple",
+                (dominant.0 - 1.0).abs() < TOLERANCE
+                    && dominant.1 == 0.0
+                    && magnitude(subdominant) < 1.0 - TOLERANCE,
+            ),
+            (
+                "subdominant eigenvalue is real and double",
+                subdominant.1 == 0.0 && multiplicity == 2 && vectors.len() == 2,
+            ),
+            (
+                "subdominant eigenvalue is larger than the rest",
+                rest < magnitude(subdominant) - TOLERANCE,
+            ),
+            (
+                "characteristic map is regular and injective",
+                characteristic_map
+                    .as_ref()
+                    .is_some_and(|map| is_regular(scheme, n, map)),
+            ),
+        ];
+
+        Analysis {
+            scheme,
+            valence,
+            sharp,
+            matrix,
+            eigenvalues,
+            subdominant,
+            multiplicity,
+            eigenvectors: vectors.len(),
+            characteristic_map,
+            conditions,
+        }
+    }
+
+    /// Whether all the conditions for a tangent plane at the limit point
+    /// hold.
+    pub fn is_c1(&self) -> bool {
+        self.conditions.iter().all(|&(_, holds)| holds)
+    }
+
+    /// The control net of the characteristic map as a flat mesh, for the
+    /// viewer or the other commands to subdivide.
+    pub fn characteristic_mesh(&self) -> Option<Mesh> {
+        let map = self.characteristic_map.as_ref()?;
+        Some(Mesh {
+            positions: map
+                .iter()
+                .map(|p| [p[0] as f32, p[1] as f32, 0.0])
+                .collect(),
+            faces: one_ring(self.scheme, self.valence).1,
+            ..Default::default()
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        let pair = |(a, b): (f64, f64)| json::array([json::number(a), json::number(b)]);
+        let rows = self
+            .matrix
+            .iter()
+            .map(|row| json::array(row.iter().map(|&x| json::number(x))));
+        let eigenvalues = self.eigenvalues.iter().map(|&e| pair(e));
+        let conditions: Vec<String> = self
+            .conditions
+            .iter()
+            .map(|(name, holds)| format!("{}:{}", json::string(name), holds))
+            .collect();
+        let map = match &self.characteristic_map {
+            Some(map) => json::array(map.iter().map(|p| pair((p[0], p[1])))),
+            None => "null".to_string(),
+        };
+        format!(
+            r#"{{"scheme":{},"valence":{},"sharp":{},"matrix":{},"eigenvalues":{},"subdominant":{},"multiplicity":{},"eigenvectors":{},"conditions":{{{}}},"c1":{},"characteristic_map":{}}}"#,
+            json::string(&self.scheme.to_string()),
+            self.valence,
+            json::array(self.sharp.iter().map(usize::to_string)),
+            json::array(rows),
+            json::array(eigenvalues),
+            pair(self.subdominant),
+            self.multiplicity,
+            self.eigenvectors,
+            conditions.join(","),
+            self.is_c1(),
+            map
+        )
+    }
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sharp: Vec<String> = self.sharp.iter().map(usize::to_string).collect();
+        writeln!(
+            f,
+            "{}, valence {}, sharp spokes: {}",
+            self.scheme,
+            self.valence,
+            if sharp.is_empty() {
+                "none".to_string()
+            } else {
+                sharp.join(", ")
+            }
+        )?;
+        writeln!(f, "subdivision matrix:     {0} x {0}", self.matrix.len())?;
+        let eigenvalues: Vec<String> = self.eigenvalues.iter().map(|&e| complex(e)).collect();
+        writeln!(f, "eigenvalues:            {}", eigenvalues.join(", "))?;
+        writeln!(
+            f,
+            "subdominant eigenvalue: {} (multiplicity {}, {} eigenvectors)",
+            complex(self.subdominant),
+            self.multiplicity,
+            self.eigenvectors
+        )?;
+        writeln!(f, "C1 conditions:")?;
+        for (name, holds) in &self.conditions {
+            writeln!(f, "  {:<48}{}", name, if *holds { "yes" } else { "no" })?;
+        }
+        match &self.characteristic_map {
+            Some(map) => {
+                writeln!(f, "characteristic map control net:")?;
+                for (i, p) in map.iter().enumerate() {
+                    writeln!(f, "  {:>3}: {:>9.6} {:>9.6}", i, p[0], p[1])?;
+                }
+            }
+            None => writeln!(f, "characteristic map:     none")?,
+        }
+        write!(
+            f,
+            "tangent plane continuous: {}",
+            if self.is_c1() { "yes" } else { "no" }
+        )
+    }
+}
+
+fn magnitude(e: (f64, f64)) -> f64 {
+    e.0.hypot(e.1)
+}
+
+/// Rounding error can split a double real eigenvalue into a pair with tiny
+/// imaginary parts, which are printed as real.
+fn complex(e: (f64, f64)) -> String {
+    if e.1.abs() < TOLERANCE {
+        format!("{:.6}", e.0)
+    } else {
+        format!("{:.6}{:+.6}i", e.0, e.1)
+    }
+}
+
+/// A flat one-ring of valence `n`: for Loop a fan of triangles, for
+/// Catmull-Clark a fan of quads, both numbered as in `Analysis`.
+fn one_ring(scheme: Scheme, n: usize) -> (Vec<[f32; 3]>, Vec<Vec<usize>>) {
+    let point = |angle: f64, radius: f64| {
+        let a = 2.0 * PI * angle / n as f64;
+        [(radius * a.cos()) as f32, (radius * a.sin()) as f32, 0.0]
+    };
+    let mut positions = vec![[0.0, 0.0, 0.0]];
+    positions.extend((0..n).map(|i| point(i as f64, 1.0)));
+    let faces = match scheme {
+        Scheme::Loop => (0..n).map(|i| vec![0, 1 + i, 1 + (i + 1) % n]).collect(),
+        Scheme::CatmullClark => {
+            positions.extend((0..n).map(|i| point(i as f64 + 0.5, 1.5)));
+            (0..n)
+                .map(|i| vec![0, 1 + i, 1 + n + i, 1 + (i + 1) % n])
+                .collect()
+        }
+    };
+    (positions, faces)
+}
+
+/// Places the one-ring at the two eigenvectors, centred, rescaled so the
+/// ring is round, turning counter-clockwise and with its first neighbour on
+/// the x axis. Any linear map of the eigenvectors spans the same space, so
+/// this only picks a readable one.
+fn normalize(scheme: Scheme, n: usize, u: &[f64], v: &[f64]) -> Vec<[f64; 2]> {
+    let mut points: Vec<[f64; 2]> = u
+        .iter()
+        .zip(v)
+        .map(|(&x, &y)| [x - u[0], y - v[0]])
+        .collect();
+
+    // whiten with the inverse square root of the covariance
+    let (mut xx, mut xy, mut yy) = (0.0, 0.0, 0.0);
+    for p in &points[1..] {
+        xx += p[0] * p[0];
+        xy += p[0] * p[1];
+        yy += p[1] * p[1];
+    }
+    let trace = xx + yy;
+    let det = xx * yy - xy * xy;
+    if det > 0.0 {
+        let s = det.sqrt();
+        let t = (trace + 2.0 * s).sqrt();
+        // the square root of [[xx, xy], [xy, yy]], and its inverse
+        let root = [[(xx + s) / t, xy / t], [xy / t, (yy + s) / t]];
+        let inverse_det = 1.0 / (root[0][0] * root[1][1] - root[0][1] * root[1][0]);
+        for p in points.iter_mut() {
+            *p = [
+                inverse_det * (root[1][1] * p[0] - root[0][1] * p[1]),
+                inverse_det * (-root[1][0] * p[0] + root[0][0] * p[1]),
+            ];
+        }
+    }
+
+    if winding(scheme, n, &points) < 0.0 {
+        for p in points.iter_mut() {
+            p[1] = -p[1];
+        }
+    }
+    let a = points[1][1].atan2(points[1][0]);
+    let (sin, cos) = (-a).sin_cos();
+    let scale = 1.0 / (points[1][0].hypot(points[1][1])).max(f64::MIN_POSITIVE);
+    // rounding error is snapped to zero so that printed nets stay readable
+    let snap = |x: f64| if x.abs() < 1e-12 { 0.0 } else { x };
+    for p in points.iter_mut() {
+        *p = [
+            snap(scale * (cos * p[0] - sin * p[1])),
+            snap(scale * (sin * p[0] + cos * p[1])),
+        ];
+    }
+    points
+}
+
+/// The ring around the centre in order: the neighbours, with the vertices
+/// across each face in between for Catmull-Clark.
+fn boundary(scheme: Scheme, n: usize) -> Vec<usize> {
+    match scheme {
+        Scheme::Loop => (1..=n).collect(),
+        Scheme::CatmullClark => (0..n).flat_map(|i| [1 + i, 1 + n + i]).collect(),
+    }
+}
+
+/// Total angle the ring turns through around the centre.
+fn winding(scheme: Scheme, n: usize, points: &[[f64; 2]]) -> f64 {
+    let ring = boundary(scheme, n);
+    (0..ring.len())
+        .map(|i| {
+            let a = points[ring[i]];
+            let b = points[ring[(i + 1) % ring.len()]];
+            (a[0] * b[1] - a[1] * b[0]).atan2(a[0] * b[0] + a[1] * b[1])
+        })
+        .sum()
+}
+
+/// A discrete stand-in for the regularity and injectivity of the
+/// characteristic map: every face of its control net keeps its orientation,
+/// and the ring goes around the centre exactly once.
+fn is_regular(scheme: Scheme, n: usize, points: &[[f64; 2]]) -> bool {
+    let faces_turn = one_ring(scheme, n).1.iter().all(|face| {
+        let area: f64 = (0..face.len())
+            .map(|i| {
+                let a = points[face[i]];
+                let b = points[face[(i + 1) % face.len()]];
+                a[0] * b[1] - a[1] * b[0]
+            })
+            .sum();
+        area > TOLERANCE
+    });
+    faces_turn && (winding(scheme, n, points) - 2.0 * PI).abs() < 1e-3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regular_vertices_are_c1() {
+        for (scheme, valence) in [(Scheme::Loop, 6), (Scheme::CatmullClark, 4)] {
+            let analysis = Analysis::new(scheme, valence, &[]);
+            let leading: Vec<f64> = analysis.eigenvalues[..4].iter().map(|e| e.0).collect();
+            for (value, expected) in leading.iter().zip([1.0, 0.5, 0.5, 0.25]) {
+                assert!((value - expected).abs() < 1e-9, "{}: {:?}", scheme, leading);
+            }
+            assert_eq!((analysis.multiplicity, analysis.eigenvectors), (2, 2));
+            assert!(analysis.is_c1(), "{}", scheme);
+        }
+    }
+}
@@ -0,0 +1,389 @@
+use crate::analysis::Analysis;
+use crate::cli::{
+    AnalyzeArgs, CompareArgs, ConvertArgs, DeformArgs, InputArgs, MatrixArgs, RepairArgs,
+    StatsArgs, SubdivideArgs, TraceArgs, ValidateArgs,
+};
+use crate::compare::{self, Comparison};
+use crate::json;
+use crate::mesh::{self, Format, Mesh};
+use crate::ptex;
+use crate::repair;
+use crate::scheme::{self, Scheme};
+use crate::stats::Stats;
+use crate::stencil::StencilTable;
+use crate::validate;
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Loads the input and runs the repair pass on it if one was asked for.
+fn load(input: &InputArgs, repair: &RepairArgs) -> Result<Mesh, Box<dyn Error>> {
+    let mut mesh = mesh::load_with(&input.file, input.weld)?;
+    repair::check(&mut mesh, repair.options(input.weld));
+    Ok(mesh)
+}
+
+/// Runs the viewer's pipeline on a file and writes the result without
+/// creating a window. `.usda` output gets the control cage, for the renderer
+/// to subdivide. Meshes that fail validation are refused unless `--force`.
+pub fn subdivide(args: &SubdivideArgs) -> Result<(), Box<dyn Error>> {
+    let base = load(&args.input, &args.repair)?;
+    validate::check(&base, args.force)?;
+    let scheme = args
+        .subdivision
+        .scheme
+        .unwrap_or_else(|| Scheme::detect(&base));
+    let creases = args.subdivision.creases();
+    let textures = ptex::load(args.textures.source(), &base, scheme)?;
+    let run = |levels| {
+        let mesh = scheme::subdivide(scheme, &base, args.linear, levels, creases);
+        match &textures {
+            Some(textures) => textures.apply(&base, &mesh),
+            None => mesh,
+        }
+    };
+
+    let output = &args.output.output;
+    let format = args.output.format()?;
+    if args.all_levels && !matches!(format, Format::Gltf | Format::Glb) {
+        return Err("--all-levels needs glTF or GLB output".into());
+    }
+    if let Some(textures) = &textures {
+        if format == Format::Usda {
+            return Err(
+                "per-face textures cannot be written to USD, which the renderer subdivides".into(),
+            );
+        }
+        println!("wrote {}", textures.save_atlas(output)?);
+    }
+    match format {
+        Format::Usda => mesh::write_usda(&run(0), output, scheme, creases)?,
+        Format::Gltf | Format::Glb if args.all_levels => {
+            let levels: Vec<Mesh> = (0..=args.levels).map(run).collect();
+            let options = mesh::GltfOptions {
+                binary: format == Format::Glb,
+                ..mesh::GltfOptions::default()
+            };
+            mesh::write_gltf(&levels, output, &options)?
+        }
+        format => mesh::save_as(&run(args.levels), output, format)?,
+    }
+    Ok(())
+}
+
+/// Writes the mesh in another format without subdividing it, repairing it
+/// first if asked to.
+pub fn convert(args: &ConvertArgs) -> Result<(), Box<dyn Error>> {
+    let mesh = load(&args.input, &args.repair)?;
+    let output = &args.output.output;
+    match args.output.format()? {
+        Format::Usda => mesh::write_usda(&mesh, output, Scheme::detect(&mesh), None)?,
+        format => mesh::save_as(&mesh, output, format)?,
+    }
+    Ok(())
+}
+
+/// Prints `Stats` for the input mesh and each subdivision level asked for.
+/// Level 0 is the mesh as loaded, before any linear levels.
+pub fn stats(args: &StatsArgs) -> Result<(), Box<dyn Error>> {
+    let base = mesh::load_with(&args.input.file, args.input.weld)?;
+    if args.levels > 0 {
+        validate::check(&base, args.force)?;
+    }
+    let scheme = args
+        .subdivision
+        .scheme
+        .unwrap_or_else(|| Scheme::detect(&base));
+    let creases = args.subdivision.creases();
+
+    let mut levels = vec![Stats::compute(&base)];
+    for level in 1..=args.levels {
+        let mesh = scheme::subdivide(scheme, &base, args.linear, level, creases);
+        levels.push(Stats::compute(&mesh));
+    }
+
+    if args.json {
+        let levels: Vec<String> = levels
+            .iter()
+            .enumerate()
+            .map(|(level, stats)| format!(r#"{{"level":{},"stats":{}}}"#, level, stats.to_json()))
+            .collect();
+        println!(
+            r#"{{"file":{},"scheme":{},"levels":[{}]}}"#,
+            json::string(&args.input.file),
+            json::string(&scheme.to_string()),
+            levels.join(",")
+        );
+    } else {
+        println!("{} ({})", args.input.file, scheme);
+        for (level, stats) in levels.iter().enumerate() {
+            println!();
+            println!("level {}", level);
+            println!("{}", stats);
+        }
+    }
+    Ok(())
+}
+
+/// Prints every problem found and fails if there were any.
+pub fn validate(args: &ValidateArgs) -> Result<(), Box<dyn Error>> {
+    let mesh = mesh::load_with(&args.input.file, args.input.weld)?;
+
+    let problems = validate::validate(&mesh);
+    for problem in &problems {
+        let kind = if problem.is_error() {
+            "error"
+        } else {
+            "warning"
+        };
+        println!("{}: {}", kind, problem);
+    }
+
+    let errors = problems.iter().filter(|p| p.is_error()).count();
+    if errors > 0 {
+        Err(format!(
+            "{}: {} errors and {} warnings found",
+            args.input.file,
+            errors,
+            problems.len() - errors
+        )
+        .into())
+    } else if !problems.is_empty() {
+        println!("{}: {} warnings found", args.input.file, problems.len());
+        Ok(())
+    } else {
+        println!("{}: no problems found", args.input.file);
+        Ok(())
+    }
+}
+
+/// Samples both surfaces and prints the distances between them, optionally
+/// writing an error colour map of the first mesh.
+pub fn compare(args: &CompareArgs) -> Result<(), Box<dyn Error>> {
+    let base = mesh::load_with(&args.first, args.weld)?;
+    validate::check(&base, args.force)?;
+    let reference = mesh::load_with(&args.second, args.weld)?;
+    let scheme = args
+        .subdivision
+        .scheme
+        .unwrap_or_else(|| Scheme::detect(&base));
+    let mut first = scheme::subdivide(
+        scheme,
+        &base,
+        args.linear,
+        args.levels,
+        args.subdivision.creases(),
+    );
+
+    let comparison = Comparison::compute(&first, &reference, args.samples);
+    if args.json {
+        println!(
+            r#"{{"first":{},"second":{},"comparison":{}}}"#,
+            json::string(&args.first),
+            json::string(&args.second),
+            comparison.to_json()
+        );
+    } else {
+        println!("{} -> {}", args.first, args.second);
+        println!("{}", comparison);
+    }
+
+    if let Some(path) = &args.error_map {
+        let format = Format::from_path(path);
+        if !matches!(format, Some(Format::Ply | Format::Off)) {
+            return Err(format!("{}: error maps are written as .ply or .off", path).into());
+        }
+        let max = compare::error_map(&mut first, &reference);
+        mesh::save(&first, path)?;
+        eprintln!("wrote {}, red is a distance of {}", path, max);
+    }
+    Ok(())
+}
+
+/// Builds stencils from the rest pose once, then refines every frame with
+/// them. Frames keep the rest pose's attributes and only move its vertices.
+pub fn deform(args: &DeformArgs) -> Result<(), Box<dyn Error>> {
+    let rest = mesh::load_with(&args.rest.file, args.rest.weld)?;
+    validate::check(&rest, args.force)?;
+    let scheme = args
+        .subdivision
+        .scheme
+        .unwrap_or_else(|| Scheme::detect(&rest));
+    let table = StencilTable::new(
+        scheme,
+        &rest,
+        args.linear,
+        args.levels,
+        args.subdivision.creases(),
+    );
+
+    let outputs: Vec<String> = args
+        .frames
+        .iter()
+        .map(|frame| {
+            let name = Path::new(frame).file_name().unwrap();
+            Path::new(&args.output)
+                .join(name)
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect();
+    for (i, output) in outputs.iter().enumerate() {
+        if let Some(j) = outputs[..i].iter().position(|o| o == output) {
+            return Err(format!(
+                "{}: written for both {} and {}",
+                output, args.frames[j], args.frames[i]
+            )
+            .into());
+        }
+        let mut inputs = std::iter::once(&args.rest.file).chain(&args.frames);
+        if let Some(input) = inputs.find(|input| same_file(input, output)) {
+            return Err(format!("{}: would overwrite the input {}", output, input).into());
+        }
+    }
+
+    for (frame, output) in args.frames.iter().zip(&outputs) {
+        let posed = mesh::load_with(frame, args.rest.weld)?;
+        if posed.positions.len() != table.control_vertices {
+            return Err(format!(
+                "{}: has {} vertices, the rest pose has {}",
+                frame,
+                posed.positions.len(),
+                table.control_vertices
+            )
+            .into());
+        }
+        mesh::save(&table.refine(&rest, &posed.positions), output)?;
+    }
+    Ok(())
+}
+
+/// Whether the two paths name the same existing file.
+fn same_file(a: &str, b: &str) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Writes the subdivision of the input as a sparse matrix over its control
+/// points, with the refined faces indexing its rows.
+pub fn matrix(args: &MatrixArgs) -> Result<(), Box<dyn Error>> {
+    let base = mesh::load_with(&args.input.file, args.input.weld)?;
+    validate::check(&base, args.force)?;
+    let scheme = args
+        .subdivision
+        .scheme
+        .unwrap_or_else(|| Scheme::detect(&base));
+    let creases = args.subdivision.creases();
+    let faces = faces_path(&args.output);
+    if Path::new(&faces) == Path::new(&args.output) {
+        return Err(format!(
+            "{}: the refined faces are written there, pick another name",
+            faces
+        )
+        .into());
+    }
+    let table = StencilTable::new(scheme, &base, args.linear, args.levels, creases);
+
+    let mut comment = format!(
+        "{}: {} linear and {} {} levels",
+        args.input.file, args.linear, args.levels, scheme
+    );
+    if let Some(cosine) = creases {
+        comment += &format!(", creases below a normal dot product of {}", cosine);
+    }
+    comment += &format!(
+        "\nrows are refined vertices, columns are control vertices, faces in {}",
+        faces
+    );
+    table.write_matrix_market(&args.output, &comment)?;
+    table.write_faces(&faces)?;
+    Ok(())
+}
+
+fn faces_path(output: &str) -> String {
+    Path::new(output)
+        .with_extension("faces")
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Prints the eigen-analysis of the subdivision matrix around one vertex.
+pub fn analyze(args: &AnalyzeArgs) -> Result<(), Box<dyn Error>> {
+    if let Some(&spoke) = args.sharp.iter().find(|&&s| s >= args.valence) {
+        return Err(format!(
+            "spoke {} does not exist, a vertex of valence {} has spokes 0 to {}",
+            spoke,
+            args.valence,
+            args.valence - 1
+        )
+        .into());
+    }
+    let sharp: Vec<usize> = args.sharp.iter().map(|&s| s as usize).collect();
+    let analysis = Analysis::new(args.scheme, args.valence as usize, &sharp);
+    if args.json {
+        println!("{}", analysis.to_json());
+    } else {
+        println!("{}", analysis);
+    }
+
+    if let Some(path) = &args.characteristic_map {
+        match analysis.characteristic_mesh() {
+            Some(mesh) => mesh::save(&mesh, path)?,
+            None => {
+                return Err("the subdominant eigenvalue has no characteristic map to write".into())
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints where faces of the subdivided mesh lie in the base mesh, one line
+/// per face: its index, base face, piece of the base face, level and the
+/// child taken at each level.
+pub fn trace(args: &TraceArgs) -> Result<(), Box<dyn Error>> {
+    let base = mesh::load_with(&args.input.file, args.input.weld)?;
+    validate::check(&base, args.force)?;
+    let scheme = args
+        .subdivision
+        .scheme
+        .unwrap_or_else(|| Scheme::detect(&base));
+    let refined = scheme::subdivide(
+        scheme,
+        &base,
+        args.linear,
+        args.levels,
+        args.subdivision.creases(),
+    );
+
+    let faces: Vec<usize> = if args.face.is_empty() {
+        (0..refined.faces.len()).collect()
+    } else {
+        args.face.clone()
+    };
+    if let Some(&f) = faces.iter().find(|&&f| f >= refined.faces.len()) {
+        return Err(format!(
+            "face {} does not exist, the subdivided mesh has {} faces",
+            f,
+            refined.faces.len()
+        )
+        .into());
+    }
+
+    println!("# face base_face piece level path");
+    for f in faces {
+        let origin = &refined.origins[f];
+        let path: String = origin.path.iter().map(|c| c.to_string()).collect();
+        println!(
+            "{} {} {} {} {}",
+            f,
+            origin.face,
+            origin.piece,
+            origin.level(),
+            if path.is_empty() { "-" } else { &path }
+        );
+    }
+    Ok(())
+}
@@ -40,7 +40,7 @@ impl Camera {
     }
 
     pub fn get_perspective(&self) -> Matrix4<f32> {
-        let fov: f32 = 3.141592 / 2.0;
+        let fov: f32 = std::f32::consts::FRAC_PI_2;
         let zfar = 1024.0;
         let znear = 0.1;
 
@@ -64,7 +64,7 @@ impl Camera {
         let x = vec3_normalized(vec3_cross(up, z));
         let y = vec3_normalized(vec3_cross(z, x));
 
-        return [
+        [
             [x[0], y[0], z[0], 0.0],
             [x[1], y[1], z[1], 0.0],
             [x[2], y[2], z[2], 0.0],
@@ -74,7 +74,7 @@ impl Camera {
                 -vec3_dot(z, position),
                 1.0,
             ],
-        ];
+        ]
     }
 
     pub fn rotate(&mut self, axis: Vector3<f32>, radians: f32, pos: Vector3<f32>) {
@@ -103,8 +103,8 @@ impl Camera {
     }
 
     pub fn update(&mut self) {
-        self.rotate(self.up, self.rotate_h * 3.14 / 100.0, [0.0, 0.0, 0.0]);
-        self.rotate(self.right, self.rotate_v * 3.14 / 100.0, [0.0, 0.0, 0.0]);
+        self.rotate(self.up, self.rotate_h * std::f32::consts::PI / 100.0, [0.0, 0.0, 0.0]);
+        self.rotate(self.right, self.rotate_v * std::f32::consts::PI / 100.0, [0.0, 0.0, 0.0]);
 
         self.eye = vec3_add(self.eye, vec3_scale(self.forward, 0.1 * self.zoom));
     }
@@ -0,0 +1,526 @@
+use crate::mesh::{self, Format};
+use crate::ptex::TextureSource;
+use crate::repair::RepairOptions;
+use crate::scheme::Scheme;
+
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::ffi::OsString;
+
+#[derive(Debug, Parser)]
+#[command(name = "subdivision", about = "Catmull-Clark and Loop subdivider")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub view: ViewArgs,
+
+    /// Threads used for subdivision [default: one per core]
+    #[arg(long, global = true, value_parser = clap::value_parser!(u32).range(1..))]
+    pub threads: Option<u32>,
+}
+
+impl Cli {
+    /// Parses the command line, exiting with clap's message on errors.
+    pub fn parse_args() -> Cli {
+        Cli::try_parse_args(std::env::args_os()).unwrap_or_else(|e| e.exit())
+    }
+
+    /// The viewer's options belong to the viewer, so they may only come
+    /// before a command if they are global, like `--threads`.
+    fn try_parse_args<I, T>(args: I) -> Result<Cli, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let mut command = Cli::command();
+        let matches = command.try_get_matches_from_mut(args)?;
+        if let Some((name, _)) = matches.subcommand() {
+            let given = command.get_arguments().find(|arg| {
+                !arg.is_global_set()
+                    && matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+            });
+            if let Some(arg) = given {
+                let arg = match arg.get_long() {
+                    Some(long) => format!("--{}", long),
+                    None => arg.get_id().to_string().to_uppercase(),
+                };
+                return Err(command.error(
+                    ErrorKind::ArgumentConflict,
+                    format!("{} cannot be used with '{}'", arg, name),
+                ));
+            }
+        }
+        Cli::from_arg_matches(&matches)
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Open a mesh in the viewer (the default when no command is given)
+    View(ViewArgs),
+    /// Subdivide a mesh and write the result without opening a window
+    Subdivide(SubdivideArgs),
+    /// Convert a mesh to another file format
+    Convert(ConvertArgs),
+    /// Print topology and geometry numbers for a mesh
+    Stats(StatsArgs),
+    /// Check a mesh for problems that break subdivision
+    Validate(ValidateArgs),
+    /// Measure how far apart two mesh surfaces are
+    Compare(CompareArgs),
+    /// Subdivide posed copies of a control cage, refining its topology once
+    Deform(DeformArgs),
+    /// Write the matrix mapping control points to refined vertices
+    Matrix(MatrixArgs),
+    /// Eigen-analysis of the subdivision rules around a vertex of a given
+    /// valence
+    Analyze(AnalyzeArgs),
+    /// Print the base face and path that each subdivided face comes from
+    Trace(TraceArgs),
+}
+
+/// The subdivision settings shared by the viewer and the batch commands.
+#[derive(Debug, Args)]
+pub struct SubdivisionArgs {
+    /// Subdivision scheme [default: loop for triangle meshes, catmull-clark
+    /// otherwise]
+    #[arg(long, value_enum)]
+    pub scheme: Option<Scheme>,
+
+    /// Keep sharp creases
+    #[arg(short, long)]
+    pub creases: bool,
+
+    /// Angle in degrees between two face normals above which their shared edge
+    /// is kept sharp [default: 53.13]
+    #[arg(long, value_name = "DEGREES", value_parser = parse_angle)]
+    pub crease_angle: Option<f32>,
+}
+
+impl SubdivisionArgs {
+    /// The cosine compared against the dot product of face normals.
+    pub fn crease_cosine(&self) -> f32 {
+        self.crease_angle
+            .map_or(mesh::CREASE_COSINE, |a| a.to_radians().cos())
+    }
+
+    /// The crease threshold handed to the subdividers, if creases are on.
+    pub fn creases(&self) -> Option<f32> {
+        self.creases.then(|| self.crease_cosine())
+    }
+}
+
+/// Clean-up run on the input before it is validated and subdivided.
+#[derive(Debug, Args)]
+pub struct RepairArgs {
+    /// Weld close vertices, remove duplicate and degenerate faces and make
+    /// face orientation consistent
+    #[arg(long)]
+    pub repair: bool,
+
+    /// Also fill boundary holes with at most EDGES edges (implies --repair)
+    #[arg(long, value_name = "EDGES")]
+    pub fill_holes: Option<usize>,
+}
+
+impl RepairArgs {
+    /// The repair to run with the given weld tolerance, if any was asked for.
+    pub fn options(&self, weld: f32) -> Option<RepairOptions> {
+        (self.repair || self.fill_holes.is_some()).then_some(RepairOptions {
+            weld,
+            fill_holes: self.fill_holes,
+        })
+    }
+}
+
+/// Per-face textures for Catmull-Clark output, one per Ptex face of the
+/// input.
+#[derive(Debug, Args)]
+pub struct TextureArgs {
+    /// Directory with one image per Ptex face, named after its index
+    /// (0.png, 1.png, ...)
+    #[arg(long, value_name = "DIR", conflicts_with = "atlas")]
+    pub textures: Option<String>,
+
+    /// One image holding every Ptex face's texture
+    #[arg(long, value_name = "IMAGE", requires = "atlas_rects")]
+    pub atlas: Option<String>,
+
+    /// Text file with an `x y width height` line in pixels for each Ptex
+    /// face of the atlas
+    #[arg(long, value_name = "FILE", requires = "atlas")]
+    pub atlas_rects: Option<String>,
+}
+
+impl TextureArgs {
+    /// Where to load the textures from, if any were given.
+    pub fn source(&self) -> Option<TextureSource> {
+        match (&self.textures, &self.atlas, &self.atlas_rects) {
+            (Some(dir), _, _) => Some(TextureSource::Directory(dir.clone())),
+            (None, Some(image), Some(rects)) => Some(TextureSource::Atlas {
+                image: image.clone(),
+                rects: rects.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// A mesh file to read.
+#[derive(Debug, Args)]
+pub struct InputArgs {
+    /// Mesh to load (.obj, .ply, .stl or .off)
+    #[arg(value_parser = parse_input)]
+    pub file: String,
+
+    /// Distance below which STL corners are welded into one vertex
+    #[arg(
+        long,
+        value_name = "TOLERANCE",
+        default_value_t = mesh::DEFAULT_WELD_TOLERANCE,
+        value_parser = parse_tolerance
+    )]
+    pub weld: f32,
+}
+
+#[derive(Debug, Args)]
+pub struct ViewArgs {
+    /// Mesh to load (.obj, .ply, .stl or .off)
+    #[arg(value_parser = parse_input)]
+    pub file: Option<String>,
+
+    #[command(flatten)]
+    pub subdivision: SubdivisionArgs,
+
+    #[command(flatten)]
+    pub repair: RepairArgs,
+
+    #[command(flatten)]
+    pub textures: TextureArgs,
+
+    /// Open the mesh even if validation finds errors
+    #[arg(long)]
+    pub force: bool,
+
+    /// Reload the mesh whenever the file changes on disk
+    #[arg(long)]
+    pub watch: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct OutputArgs {
+    /// File to write
+    #[arg(short, long)]
+    pub output: String,
+
+    /// Output format [default: from the output extension]
+    #[arg(long, value_enum)]
+    pub format: Option<Format>,
+}
+
+impl OutputArgs {
+    pub fn format(&self) -> Result<Format, String> {
+        self.format
+            .or_else(|| Format::from_path(&self.output))
+            .ok_or_else(|| {
+                format!(
+                    "cannot tell the format of {} from its extension, use --format",
+                    self.output
+                )
+            })
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct SubdivideArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    #[command(flatten)]
+    pub subdivision: SubdivisionArgs,
+
+    #[command(flatten)]
+    pub repair: RepairArgs,
+
+    #[command(flatten)]
+    pub textures: TextureArgs,
+
+    /// Levels of loop / catmull-clark subdivision
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u32).range(0..=8))]
+    pub levels: u32,
+
+    /// Levels of linear subdivision, run before the smoothing levels
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u32).range(0..=8))]
+    pub linear: u32,
+
+    /// Write every level 0..=LEVELS as its own mesh (glTF output only)
+    #[arg(long)]
+    pub all_levels: bool,
+
+    /// Subdivide even if validation finds errors
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ConvertArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    #[command(flatten)]
+    pub repair: RepairArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct StatsArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub subdivision: SubdivisionArgs,
+
+    /// Also report levels 1..=LEVELS of loop / catmull-clark subdivision
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u32).range(0..=8))]
+    pub levels: u32,
+
+    /// Levels of linear subdivision, run before the smoothing levels
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u32).range(0..=8))]
+    pub linear: u32,
+
+    /// Print JSON instead of text
+    #[arg(long)]
+    pub json: bool,
+
+    /// Subdivide even if validation finds errors
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ValidateArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct CompareArgs {
+    /// Mesh to measure, after subdividing it as asked
+    #[arg(value_parser = parse_input)]
+    pub first: String,
+
+    /// Reference mesh to measure against
+    #[arg(value_parser = parse_input)]
+    pub second: String,
+
+    /// Distance below which STL corners are welded into one vertex
+    #[arg(
+        long,
+        value_name = "TOLERANCE",
+        default_value_t = mesh::DEFAULT_WELD_TOLERANCE,
+        value_parser = parse_tolerance
+    )]
+    pub weld: f32,
+
+    #[command(flatten)]
+    pub subdivision: SubdivisionArgs,
+
+    /// Levels of loop / catmull-clark subdivision applied to the first mesh
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u32).range(0..=8))]
+    pub levels: u32,
+
+    /// Levels of linear subdivision, run before the smoothing levels
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u32).range(0..=8))]
+    pub linear: u32,
+
+    /// Points sampled on each surface
+    #[arg(long, default_value_t = 20000)]
+    pub samples: usize,
+
+    /// Write the first mesh with vertices coloured by their distance to the
+    /// second (.ply or .off)
+    #[arg(long, value_name = "FILE")]
+    pub error_map: Option<String>,
+
+    /// Print JSON instead of text
+    #[arg(long)]
+    pub json: bool,
+
+    /// Subdivide even if validation finds errors
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct DeformArgs {
+    /// Control cage in its rest pose, which decides the topology and creases,
+    /// and the STL weld tolerance used for it and every frame
+    #[command(flatten)]
+    pub rest: InputArgs,
+
+    /// Posed copies of the cage, with the same vertices in the same order
+    #[arg(value_parser = parse_input, required = true)]
+    pub frames: Vec<String>,
+
+    /// Directory the subdivided frames are written to, under their own names
+    #[arg(short, long, value_name = "DIR")]
+    pub output: String,
+
+    #[command(flatten)]
+    pub subdivision: SubdivisionArgs,
+
+    /// Levels of loop / catmull-clark subdivision
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u32).range(0..=8))]
+    pub levels: u32,
+
+    /// Levels of linear subdivision, run before the smoothing levels
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u32).range(0..=8))]
+    pub linear: u32,
+
+    /// Subdivide even if validation finds errors
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct MatrixArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Matrix Market file to write; the refined faces go next to it with a
+    /// .faces extension
+    #[arg(short, long, value_name = "FILE")]
+    pub output: String,
+
+    #[command(flatten)]
+    pub subdivision: SubdivisionArgs,
+
+    /// Levels of loop / catmull-clark subdivision
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(0..=8))]
+    pub levels: u32,
+
+    /// Levels of linear subdivision, run before the smoothing levels
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u32).range(0..=8))]
+    pub linear: u32,
+
+    /// Subdivide even if validation finds errors
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct AnalyzeArgs {
+    /// Number of edges at the vertex
+    #[arg(long, value_parser = clap::value_parser!(u32).range(3..=64))]
+    pub valence: u32,
+
+    /// Subdivision scheme
+    #[arg(long, value_enum, default_value_t = Scheme::Loop)]
+    pub scheme: Scheme,
+
+    /// Spokes kept sharp, numbered from 0 around the vertex
+    #[arg(long, value_name = "SPOKES", value_delimiter = ',')]
+    pub sharp: Vec<u32>,
+
+    /// Write the control net of the characteristic map to a mesh file
+    #[arg(long, value_name = "FILE")]
+    pub characteristic_map: Option<String>,
+
+    /// Print JSON instead of text
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct TraceArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub subdivision: SubdivisionArgs,
+
+    /// Levels of loop / catmull-clark subdivision
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u32).range(0..=8))]
+    pub levels: u32,
+
+    /// Levels of linear subdivision, run before the smoothing levels
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u32).range(0..=8))]
+    pub linear: u32,
+
+    /// Subdivided faces to trace [default: all of them]
+    #[arg(long, value_name = "FACES", value_delimiter = ',')]
+    pub face: Vec<usize>,
+
+    /// Subdivide even if validation finds errors
+    #[arg(long)]
+    pub force: bool,
+}
+
+fn parse_input(path: &str) -> Result<String, String> {
+    if mesh::is_supported(path) {
+        Ok(path.to_string())
+    } else {
+        Err("expected a .obj, .ply, .stl or .off file".to_string())
+    }
+}
+
+fn parse_angle(angle: &str) -> Result<f32, String> {
+    match angle.parse::<f32>() {
+        Ok(a) if a > 0.0 && a < 180.0 => Ok(a),
+        _ => Err("expected an angle between 0 and 180 degrees".to_string()),
+    }
+}
+
+fn parse_tolerance(tolerance: &str) -> Result<f32, String> {
+    match tolerance.parse::<f32>() {
+        Ok(t) if t > 0.0 && t.is_finite() => Ok(t),
+        _ => Err("expected a positive distance".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn threads_go_before_or_after_the_command() {
+        for args in [
+            &["subdivision", "--threads", "2", "stats", "assets/cube.obj"][..],
+            &["subdivision", "stats", "--threads", "2", "assets/cube.obj"],
+        ] {
+            let cli = Cli::try_parse_args(args).unwrap();
+            assert_eq!(cli.threads, Some(2));
+            assert!(matches!(cli.command, Some(Command::Stats(_))));
+        }
+
+        let cli =
+            Cli::try_parse_args(["subdivision", "--threads", "2", "assets/cube.obj"]).unwrap();
+        assert_eq!(cli.view.file.as_deref(), Some("assets/cube.obj"));
+        assert!(cli.command.is_none());
+    }
+
+    #[test]
+    fn viewer_options_do_not_go_before_a_command() {
+        for args in [
+            &[
+                "subdivision",
+                "--scheme",
+                "loop",
+                "stats",
+                "assets/cube.obj",
+            ][..],
+            &["subdivision", "--creases", "stats", "assets/cube.obj"],
+        ] {
+            let error = Cli::try_parse_args(args).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::ArgumentConflict);
+        }
+    }
+}
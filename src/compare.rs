@@ -0,0 +1,405 @@
+use crate::json;
+use crate::mesh::Mesh;
+
+use std::fmt;
+use vecmath::*;
+
+/// Distances from points sampled on one surface to the closest points of
+/// another.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Distances {
+    pub max: f32,
+    pub mean: f32,
+    pub rms: f32,
+    /// Mean and max angle in degrees between the normals at each sample and
+    /// at its closest point.
+    pub normal_mean: f32,
+    pub normal_max: f32,
+}
+
+impl Distances {
+    fn to_json(self) -> String {
+        format!(
+            r#"{{"max":{},"mean":{},"rms":{},"normal_mean":{},"normal_max":{}}}"#,
+            json::number(self.max),
+            json::number(self.mean),
+            json::number(self.rms),
+            json::number(self.normal_mean),
+            json::number(self.normal_max)
+        )
+    }
+}
+
+/// How far apart two surfaces are, measured both ways.
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    /// From the first mesh to the second.
+    pub forward: Distances,
+    /// From the second mesh to the first.
+    pub backward: Distances,
+    pub hausdorff: f32,
+    pub mean: f32,
+    pub rms: f32,
+    /// Bounding box diagonal of the second mesh, for scale.
+    pub diagonal: f32,
+}
+
+impl Comparison {
+    /// Samples about `samples` points on each mesh.
+    pub fn compute(a: &Mesh, b: &Mesh, samples: usize) -> Comparison {
+        let a = Surface::new(a);
+        let b = Surface::new(b);
+        let forward = a.distances_to(&b, samples);
+        let backward = b.distances_to(&a, samples);
+
+        // both directions use the same number of samples
+        let mean = (forward.mean + backward.mean) / 2.0;
+        let rms = ((forward.rms * forward.rms + backward.rms * backward.rms) / 2.0).sqrt();
+        Comparison {
+            forward,
+            backward,
+            hausdorff: forward.max.max(backward.max),
+            mean,
+            rms,
+            diagonal: if b.triangles.is_empty() {
+                0.0
+            } else {
+                vec3_len(vec3_sub(b.tree.max, b.tree.min))
+            },
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"forward":{},"backward":{},"hausdorff":{},"mean":{},"rms":{},"diagonal":{}}}"#,
+            self.forward.to_json(),
+            self.backward.to_json(),
+            json::number(self.hausdorff),
+            json::number(self.mean),
+            json::number(self.rms),
+            json::number(self.diagonal)
+        )
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, d) in [
+            ("first -> second", self.forward),
+            ("second -> first", self.backward),
+        ] {
+            writeln!(f, "{}:", name)?;
+            writeln!(f, "  hausdorff:              {}", d.max)?;
+            writeln!(f, "  mean distance:          {}", d.mean)?;
+            writeln!(f, "  rms distance:           {}", d.rms)?;
+            writeln!(
+                f,
+                "  normal deviation:       mean {:.3}°, max {:.3}°",
+                d.normal_mean, d.normal_max
+            )?;
+        }
+        writeln!(f, "symmetric:")?;
+        writeln!(f, "  hausdorff:              {}", self.hausdorff)?;
+        writeln!(f, "  mean distance:          {}", self.mean)?;
+        writeln!(f, "  rms distance:           {}", self.rms)?;
+        write!(f, "bounding box diagonal:    {}", self.diagonal)
+    }
+}
+
+/// Colours each vertex of `mesh` by its distance to `reference`, from blue at
+/// zero through green to red at the largest distance. Returns that distance.
+pub fn error_map(mesh: &mut Mesh, reference: &Mesh) -> f32 {
+    let reference = Surface::new(reference);
+    let distances: Vec<f32> = mesh
+        .positions
+        .iter()
+        .map(|&p| vec3_len(vec3_sub(reference.closest(p).0, p)))
+        .collect();
+    let max = distances.iter().fold(0.0f32, |m, &d| m.max(d));
+
+    mesh.colors = Some(
+        distances
+            .iter()
+            .map(|&d| {
+                let t = if max > 0.0 { d / max } else { 0.0 };
+                if t < 0.5 {
+                    [0.0, 2.0 * t, 1.0 - 2.0 * t]
+                } else {
+                    [2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0]
+                }
+            })
+            .collect(),
+    );
+    max
+}
+
+/// A mesh split into triangles, with a bounding volume tree over them.
+struct Surface {
+    triangles: Vec<[[f32; 3]; 3]>,
+    normals: Vec<[f32; 3]>,
+    tree: Node,
+}
+
+/// Bounding box over `triangles[start..end]`, split in two unless small.
+struct Node {
+    min: [f32; 3],
+    max: [f32; 3],
+    start: usize,
+    end: usize,
+    children: Option<Box<[Node; 2]>>,
+}
+
+impl Surface {
+    fn new(mesh: &Mesh) -> Surface {
+        let mut triangles = Vec::new();
+        for face in &mesh.faces {
+            for i in 1..face.len().saturating_sub(1) {
+                triangles.push([
+                    mesh.positions[face[0]],
+                    mesh.positions[face[i]],
+                    mesh.positions[face[i + 1]],
+                ]);
+            }
+        }
+        let tree = Node::build(&mut triangles, 0);
+        let normals = triangles
+            .iter()
+            .map(|t| {
+                let n = vec3_cross(vec3_sub(t[1], t[0]), vec3_sub(t[2], t[0]));
+                let len = vec3_len(n);
+                if len > 0.0 {
+                    vec3_scale(n, 1.0 / len)
+                } else {
+                    n
+                }
+            })
+            .collect();
+        Surface {
+            triangles,
+            normals,
+            tree,
+        }
+    }
+
+    /// The closest point of the surface to `p`, and the triangle it lies on.
+    fn closest(&self, p: [f32; 3]) -> ([f32; 3], usize) {
+        let mut best = (p, 0, f32::INFINITY);
+        self.tree.closest(&self.triangles, p, &mut best);
+        (best.0, best.1)
+    }
+
+    /// Spreads `samples` points over the surface by area, using a fixed
+    /// low-discrepancy sequence so results repeat between runs.
+    fn distances_to(&self, other: &Surface, samples: usize) -> Distances {
+        let areas: Vec<f32> = self
+            .triangles
+            .iter()
+            .map(|t| vec3_len(vec3_cross(vec3_sub(t[1], t[0]), vec3_sub(t[2], t[0]))))
+            .collect();
+        let total: f32 = areas.iter().sum();
+        if total <= 0.0 || samples == 0 || other.triangles.is_empty() {
+            return Distances::default();
+        }
+
+        let mut d = Distances::default();
+        let (mut sum, mut sum_squares, mut angles) = (0.0f64, 0.0f64, 0.0f64);
+        let mut t = 0;
+        let mut covered = areas[0];
+        for i in 0..samples {
+            let target = (i as f32 + 0.5) / samples as f32 * total;
+            while covered < target && t + 1 < areas.len() {
+                t += 1;
+                covered += areas[t];
+            }
+
+            // the R2 sequence, folded into the triangle
+            let mut u = (0.5 + i as f64 * 0.754_877_666_2).fract() as f32;
+            let mut v = (0.5 + i as f64 * 0.569_840_290_9).fract() as f32;
+            if u + v > 1.0 {
+                u = 1.0 - u;
+                v = 1.0 - v;
+            }
+            let [a, b, c] = self.triangles[t];
+            let p = vec3_add(
+                a,
+                vec3_add(vec3_scale(vec3_sub(b, a), u), vec3_scale(vec3_sub(c, a), v)),
+            );
+
+            let (q, other_t) = other.closest(p);
+            let distance = vec3_len(vec3_sub(q, p));
+            let cos = vec3_dot(self.normals[t], other.normals[other_t]).clamp(-1.0, 1.0);
+            let angle = cos.acos().to_degrees();
+
+            d.max = d.max.max(distance);
+            d.normal_max = d.normal_max.max(angle);
+            sum += distance as f64;
+            sum_squares += (distance * distance) as f64;
+            angles += angle as f64;
+        }
+
+        let n = samples as f64;
+        d.mean = (sum / n) as f32;
+        d.rms = (sum_squares / n).sqrt() as f32;
+        d.normal_mean = (angles / n) as f32;
+        d
+    }
+}
+
+impl Node {
+    fn build(triangles: &mut [[[f32; 3]; 3]], start: usize) -> Node {
+        let mut min = [f32::INFINITY; 3];
+        let mut max = [f32::NEG_INFINITY; 3];
+        for t in triangles.iter() {
+            for p in t {
+                for k in 0..3 {
+                    min[k] = min[k].min(p[k]);
+                    max[k] = max[k].max(p[k]);
+                }
+            }
+        }
+
+        let mut node = Node {
+            min,
+            max,
+            start,
+            end: start + triangles.len(),
+            children: None,
+        };
+        if triangles.len() > 4 {
+            let extent = vec3_sub(max, min);
+            let axis = (0..3)
+                .max_by(|&a, &b| extent[a].total_cmp(&extent[b]))
+                .unwrap();
+            let centre = |t: &[[f32; 3]; 3]| t[0][axis] + t[1][axis] + t[2][axis];
+            triangles.sort_unstable_by(|a, b| centre(a).total_cmp(&centre(b)));
+            let mid = triangles.len() / 2;
+            let (left, right) = triangles.split_at_mut(mid);
+            node.children = Some(Box::new([
+                Node::build(left, start),
+                Node::build(right, start + mid),
+            ]));
+        }
+        node
+    }
+
+    fn distance_squared(&self, p: [f32; 3]) -> f32 {
+        (0..3)
+            .map(|k| {
+                let d = (self.min[k] - p[k]).max(p[k] - self.max[k]).max(0.0);
+                d * d
+            })
+            .sum()
+    }
+
+    fn closest(&self, triangles: &[[[f32; 3]; 3]], p: [f32; 3], best: &mut ([f32; 3], usize, f32)) {
+        if self.distance_squared(p) >= best.2 {
+            return;
+        }
+        match &self.children {
+            Some(children) => {
+                let [a, b] = &**children;
+                let (near, far) = if a.distance_squared(p) <= b.distance_squared(p) {
+                    (a, b)
+                } else {
+                    (b, a)
+                };
+                near.closest(triangles, p, best);
+                far.closest(triangles, p, best);
+            }
+            None => {
+                for (t, triangle) in (self.start..).zip(&triangles[self.start..self.end]) {
+                    let q = closest_on_triangle(p, triangle);
+                    let d = vec3_square_len(vec3_sub(q, p));
+                    if d < best.2 {
+                        *best = (q, t, d);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Closest point to `p` on a triangle, from Ericson's Real-Time Collision
+/// Detection.
+fn closest_on_triangle(p: [f32; 3], t: &[[f32; 3]; 3]) -> [f32; 3] {
+    let [a, b, c] = *t;
+    let ab = vec3_sub(b, a);
+    let ac = vec3_sub(c, a);
+    let ap = vec3_sub(p, a);
+    let d1 = vec3_dot(ab, ap);
+    let d2 = vec3_dot(ac, ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = vec3_sub(p, b);
+    let d3 = vec3_dot(ab, bp);
+    let d4 = vec3_dot(ac, bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return vec3_add(a, vec3_scale(ab, d1 / (d1 - d3)));
+    }
+
+    let cp = vec3_sub(p, c);
+    let d5 = vec3_dot(ab, cp);
+    let d6 = vec3_dot(ac, cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return vec3_add(a, vec3_scale(ac, d2 / (d2 - d6)));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+        return vec3_add(b, vec3_scale(vec3_sub(c, b), w));
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    let v = vb * denom;
+    let w = vc * denom;
+    vec3_add(a, vec3_add(vec3_scale(ab, v), vec3_scale(ac, w)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quad(z: f32) -> Mesh {
+        Mesh {
+            positions: vec![[0.0, 0.0, z], [1.0, 0.0, z], [1.0, 1.0, z], [0.0, 1.0, z]],
+            faces: vec![vec![0, 1, 2, 3]],
+            ..Mesh::default()
+        }
+    }
+
+    #[test]
+    fn a_mesh_is_no_distance_from_itself() {
+        let mesh = crate::mesh::load("assets/quad_cube.obj").unwrap();
+        let c = Comparison::compute(&mesh, &mesh, 1000);
+        // the closest points are found through barycentric coordinates, which
+        // round off a little
+        for d in [&c.forward, &c.backward] {
+            assert!(d.max < 1e-5 && d.mean < 1e-5 && d.rms < 1e-5);
+            assert!(d.normal_max < 1e-3);
+        }
+        assert!(c.hausdorff < 1e-5 && c.mean < 1e-5 && c.rms < 1e-5);
+    }
+
+    #[test]
+    fn offset_quad_is_the_offset_away_everywhere() {
+        let d = 0.25;
+        let c = Comparison::compute(&quad(0.0), &quad(d), 1000);
+        for x in [c.hausdorff, c.mean, c.rms, c.forward.max, c.backward.mean] {
+            assert!((x - d).abs() < 1e-5, "{} != {}", x, d);
+        }
+        assert!(c.forward.normal_max < 1e-3);
+    }
+}
@@ -0,0 +1,323 @@
+/// Eigenvalues of a square matrix as (real, imaginary) pairs, in no
+/// particular order. The matrix is reduced to upper Hessenberg form and the
+/// eigenvalues found with Francis double-shift QR steps.
+pub fn eigenvalues(matrix: &[Vec<f64>]) -> Vec<(f64, f64)> {
+    let mut a = matrix.to_vec();
+    hessenberg(&mut a);
+    hessenberg_eigenvalues(a)
+}
+
+/// Reduces `a` to upper Hessenberg form by elimination with pivoting, which
+/// keeps its eigenvalues.
+fn hessenberg(a: &mut [Vec<f64>]) {
+    let n = a.len();
+    for m in 1..n.saturating_sub(1) {
+        let mut x = 0.0;
+        let mut pivot = m;
+        for (j, row) in a.iter().enumerate().skip(m) {
+            if row[m - 1].abs() > f64::abs(x) {
+                x = row[m - 1];
+                pivot = j;
+            }
+        }
+        if pivot != m {
+            a.swap(pivot, m);
+            for row in a.iter_mut() {
+                row.swap(pivot, m);
+            }
+        }
+        if x != 0.0 {
+            for i in m + 1..n {
+                let y = a[i][m - 1] / x;
+                if y != 0.0 {
+                    let (above, below) = a.split_at_mut(i);
+                    below[0][m - 1] = 0.0;
+                    for (x, p) in below[0][m..].iter_mut().zip(&above[m][m..]) {
+                        *x -= y * p;
+                    }
+                    for row in a.iter_mut() {
+                        row[m] += y * row[i];
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The eigenvalues of an upper Hessenberg matrix, deflating one or two at a
+/// time from the bottom.
+#[allow(clippy::needless_range_loop)]
+fn hessenberg_eigenvalues(mut a: Vec<Vec<f64>>) -> Vec<(f64, f64)> {
+    let n = a.len();
+    let mut r = vec![(0.0, 0.0); n];
+    let mut norm = 0.0;
+    for (i, row) in a.iter().enumerate() {
+        for x in &row[i.saturating_sub(1)..] {
+            norm += x.abs();
+        }
+    }
+
+    // shifts already taken out of the diagonal
+    let mut t = 0.0;
+    let mut nn = n;
+    while nn > 0 {
+        let last = nn - 1;
+        let mut iterations = 0;
+        loop {
+            // the lowest negligible subdiagonal element splits off the
+            // block l..=last
+            let mut l = last;
+            while l > 0 {
+                let s = a[l - 1][l - 1].abs() + a[l][l].abs();
+                let s = if s == 0.0 { norm } else { s };
+                if a[l][l - 1].abs() + s == s {
+                    a[l][l - 1] = 0.0;
+                    break;
+                }
+                l -= 1;
+            }
+
+            let mut x = a[last][last];
+            if l == last {
+                r[last] = (x + t, 0.0);
+                nn -= 1;
+                break;
+            }
+            let mut y = a[last - 1][last - 1];
+            let mut w = a[last][last - 1] * a[last - 1][last];
+            if l == last - 1 {
+                let p = 0.5 * (y - x);
+                let q = p * p + w;
+                let z = q.abs().sqrt();
+                x += t;
+                if q >= 0.0 {
+                    let z = p + z.copysign(p);
+                    let low = if z != 0.0 { x - w / z } else { x + z };
+                    r[last - 1] = (x + z, 0.0);
+                    r[last] = (low, 0.0);
+                } else {
+                    r[last - 1] = (x + p, -z);
+                    r[last] = (x + p, z);
+                }
+                nn -= 2;
+                break;
+            }
+
+            assert!(iterations < 60, "QR iteration did not converge");
+            if iterations == 10 || iterations == 20 {
+                // exceptional shift, to break cycles
+                t += x;
+                for (i, row) in a.iter_mut().enumerate().take(last + 1) {
+                    row[i] -= x;
+                }
+                let s = a[last][last - 1].abs() + a[last - 1][last - 2].abs();
+                x = 0.75 * s;
+                y = x;
+                w = -0.4375 * s * s;
+            }
+            iterations += 1;
+
+            // look for two consecutive small subdiagonal elements, to start
+            // the step lower down
+            let mut m = last - 2;
+            let (mut p, mut q, mut r3);
+            loop {
+                let z = a[m][m];
+                let rr = x - z;
+                let s = y - z;
+                p = (rr * s - w) / a[m + 1][m] + a[m][m + 1];
+                q = a[m + 1][m + 1] - z - rr - s;
+                r3 = a[m + 2][m + 1];
+                let s = p.abs() + q.abs() + r3.abs();
+                p /= s;
+                q /= s;
+                r3 /= s;
+                if m == l {
+                    break;
+                }
+                let u = a[m][m - 1].abs() * (q.abs() + r3.abs());
+                let v = p.abs() * (a[m - 1][m - 1].abs() + z.abs() + a[m + 1][m + 1].abs());
+                if u + v == v {
+                    break;
+                }
+                m -= 1;
+            }
+            for i in m + 2..=last {
+                a[i][i - 2] = 0.0;
+                if i != m + 2 {
+                    a[i][i - 3] = 0.0;
+                }
+            }
+
+            // double shifted QR step on rows l..=last and columns m..=last
+            for k in m..last {
+                if k != m {
+                    p = a[k][k - 1];
+                    q = a[k + 1][k - 1];
+                    r3 = if k != last - 1 { a[k + 2][k - 1] } else { 0.0 };
+                    x = p.abs() + q.abs() + r3.abs();
+                    if x != 0.0 {
+                        p /= x;
+                        q /= x;
+                        r3 /= x;
+                    }
+                }
+                let s = (p * p + q * q + r3 * r3).sqrt().copysign(p);
+                if s == 0.0 {
+                    continue;
+                }
+                if k == m {
+                    if l != m {
+                        a[k][k - 1] = -a[k][k - 1];
+                    }
+                } else {
+                    a[k][k - 1] = -s * x;
+                }
+                p += s;
+                x = p / s;
+                y = q / s;
+                let z = r3 / s;
+                q /= p;
+                r3 /= p;
+                for j in k..=last {
+                    let mut p = a[k][j] + q * a[k + 1][j];
+                    if k != last - 1 {
+                        p += r3 * a[k + 2][j];
+                        a[k + 2][j] -= p * z;
+                    }
+                    a[k + 1][j] -= p * y;
+                    a[k][j] -= p * x;
+                }
+                for row in &mut a[l..=last.min(k + 3)] {
+                    let mut p = x * row[k] + y * row[k + 1];
+                    if k != last - 1 {
+                        p += z * row[k + 2];
+                        row[k + 2] -= p * r3;
+                    }
+                    row[k + 1] -= p * q;
+                    row[k] -= p;
+                }
+            }
+        }
+    }
+    r
+}
+
+/// A basis of the vectors `v` with `matrix · v = value · v`, found by
+/// elimination with full pivoting. Pivots below `tolerance` times the largest
+/// entry count as zero.
+pub fn eigenvectors(matrix: &[Vec<f64>], value: f64, tolerance: f64) -> Vec<Vec<f64>> {
+    let n = matrix.len();
+    let mut a: Vec<Vec<f64>> = matrix.to_vec();
+    for (i, row) in a.iter_mut().enumerate() {
+        row[i] -= value;
+    }
+    let largest = a.iter().flatten().fold(0.0f64, |m, x| m.max(x.abs()));
+    let tolerance = tolerance * largest.max(1.0);
+
+    // columns[c] is the unknown held in column c after the swaps
+    let mut columns: Vec<usize> = (0..n).collect();
+    let mut rank = 0;
+    while rank < n {
+        let mut best = (rank, rank, 0.0);
+        for (i, row) in a.iter().enumerate().skip(rank) {
+            for (j, x) in row.iter().enumerate().skip(rank) {
+                if x.abs() > best.2 {
+                    best = (i, j, x.abs());
+                }
+            }
+        }
+        if best.2 <= tolerance {
+            break;
+        }
+        a.swap(rank, best.0);
+        for row in a.iter_mut() {
+            row.swap(rank, best.1);
+        }
+        columns.swap(rank, best.1);
+
+        let pivot = a[rank][rank];
+        for x in a[rank].iter_mut() {
+            *x /= pivot;
+        }
+        let pivot_row = a[rank].clone();
+        for (i, row) in a.iter_mut().enumerate() {
+            let f = row[rank];
+            if i != rank && f != 0.0 {
+                for (x, p) in row.iter_mut().zip(&pivot_row) {
+                    *x -= f * p;
+                }
+            }
+        }
+        rank += 1;
+    }
+
+    // one vector per free unknown, solving the pivot unknowns for it
+    (rank..n)
+        .map(|free| {
+            let mut v = vec![0.0; n];
+            v[columns[free]] = 1.0;
+            for (i, row) in a.iter().enumerate().take(rank) {
+                v[columns[i]] = -row[free];
+            }
+            v
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut values: Vec<(f64, f64)>) -> Vec<(f64, f64)> {
+        values.sort_by(|a, b| b.0.total_cmp(&a.0).then(b.1.total_cmp(&a.1)));
+        values
+    }
+
+    fn assert_close(values: &[(f64, f64)], expected: &[(f64, f64)]) {
+        assert_eq!(values.len(), expected.len());
+        for (v, e) in values.iter().zip(expected) {
+            assert!(
+                (v.0 - e.0).abs() < 1e-9 && (v.1 - e.1).abs() < 1e-9,
+                "{:?} != {:?}",
+                values,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn real_and_complex_pairs() {
+        // a rotation by a quarter turn next to a shear
+        let matrix = vec![
+            vec![0.0, -1.0, 0.0, 0.0],
+            vec![1.0, 0.0, 0.0, 0.0],
+            vec![0.0, 0.0, 2.0, 1.0],
+            vec![0.0, 0.0, 0.0, 3.0],
+        ];
+        assert_close(
+            &sorted(eigenvalues(&matrix)),
+            &[(3.0, 0.0), (2.0, 0.0), (0.0, 1.0), (0.0, -1.0)],
+        );
+    }
+
+    #[test]
+    fn loop_valence_six() {
+        // the regular Loop one-ring: the centre, then its six neighbours
+        let (a, b, c) = (3.0 / 8.0, 1.0 / 8.0, 1.0 / 16.0);
+        let mut matrix = vec![vec![0.0; 7]; 7];
+        matrix[0][0] = 5.0 / 8.0;
+        for i in 1..7 {
+            matrix[0][i] = c;
+            matrix[i][0] = a;
+            matrix[i][i] = a;
+            matrix[i][i % 6 + 1] = b;
+            matrix[i][(i + 4) % 6 + 1] = b;
+        }
+        let expected = [1.0, 0.5, 0.5, 0.25, 0.25, 0.25, 0.125].map(|x| (x, 0.0));
+        assert_close(&sorted(eigenvalues(&matrix)), &expected);
+
+        assert_eq!(eigenvectors(&matrix, 1.0, 1e-9).len(), 1);
+        assert_eq!(eigenvectors(&matrix, 0.5, 1e-9).len(), 2);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::render::Vertex;
+
+use std::collections::{HashMap, HashSet};
+use vecmath::*;
+
+/// One subdivision level ready to upload: vertices shared between the faces
+/// around them, with index lists for the triangles and the edges.
+#[derive(Clone, Debug, Default)]
+pub struct Frame {
+    pub vertices: Vec<Vertex>,
+    /// Three indices per triangle.
+    pub triangles: Vec<u32>,
+    /// Two indices per edge of the polygons, each shared edge once.
+    pub edges: Vec<u32>,
+    /// The average corner, subtracted from every position.
+    pub center: [f32; 3],
+    /// Whether every face takes the colour of its triangles' last vertex
+    /// instead of blending the colours of its corners.
+    pub face_colors: bool,
+}
+
+impl Frame {
+    /// Draws each polygon in one colour. The colour goes on a corner no other
+    /// polygon uses for its own, and the polygon is fanned out from that
+    /// corner with it last in every triangle, so that it is the provoking
+    /// vertex the flat colour is taken from. A polygon whose corners are all
+    /// taken gets a copy of its first corner.
+    pub fn with_face_colors(
+        positions: &[[f32; 3]],
+        faces: &[Vec<usize>],
+        colors: &[[f32; 3]],
+    ) -> Frame {
+        let mut frame = Frame::new(positions, faces);
+        frame.face_colors = true;
+
+        let mut taken = vec![false; positions.len()];
+        for (face, &color) in faces.iter().zip(colors) {
+            let start = face.iter().position(|&v| !taken[v]).unwrap_or(0);
+            let provoking = if taken[face[start]] {
+                frame.vertices.push(frame.vertices[face[start]]);
+                frame.vertices.len() - 1
+            } else {
+                taken[face[start]] = true;
+                face[start]
+            };
+            frame.vertices[provoking].color = color;
+
+            // the corners after the provoking one, going round the face
+            let others: Vec<usize> = (1..face.len())
+                .map(|i| face[(start + i) % face.len()])
+                .collect();
+            for pair in others.windows(2) {
+                frame
+                    .triangles
+                    .extend([pair[0] as u32, pair[1] as u32, provoking as u32]);
+            }
+        }
+        frame
+    }
+
+    /// Draws the polygons with colours and texture coordinates given per
+    /// vertex and blended across each face, white where there are no
+    /// colours.
+    pub fn with_vertex_colors(
+        positions: &[[f32; 3]],
+        faces: &[Vec<usize>],
+        colors: Option<&[[f32; 3]]>,
+        uvs: Option<&[[f32; 2]]>,
+    ) -> Frame {
+        let mut frame = Frame::new(positions, faces);
+        for (i, v) in frame.vertices.iter_mut().enumerate() {
+            v.color = colors.map_or([1.0, 1.0, 1.0], |c| c[i]);
+            v.tex_coords = uvs.map_or([0.0, 0.0], |t| t[i]);
+        }
+        for face in faces {
+            for i in 1..face.len() - 1 {
+                frame
+                    .triangles
+                    .extend([face[0] as u32, face[i] as u32, face[i + 1] as u32]);
+            }
+        }
+        frame
+    }
+
+    /// The vertices, centred on the average corner, with smooth normals and
+    /// the edges, but no triangles yet.
+    fn new(positions: &[[f32; 3]], faces: &[Vec<usize>]) -> Frame {
+        let mut center = [0.0, 0.0, 0.0];
+        let mut corners = 0;
+        for face in faces {
+            for &v in face {
+                center = vec3_add(center, positions[v]);
+            }
+            corners += face.len();
+        }
+        let center = vec3_scale(center, 1.0 / corners.max(1) as f32);
+
+        let normals = smooth_normals(positions, faces);
+        let vertices = positions
+            .iter()
+            .zip(normals)
+            .map(|(&p, normal)| Vertex {
+                position: vec3_sub(p, center),
+                color: [1.0, 1.0, 1.0],
+                normal,
+                tex_coords: [0.0, 0.0],
+            })
+            .collect();
+
+        Frame {
+            vertices,
+            triangles: Vec::new(),
+            edges: edges(faces),
+            center,
+            face_colors: false,
+        }
+    }
+}
+
+/// The edges of `faces` as pairs of indices, each shared edge once.
+pub fn edges(faces: &[Vec<usize>]) -> Vec<u32> {
+    let mut seen = HashSet::new();
+    let mut r = Vec::new();
+    for face in faces {
+        for (i, &a) in face.iter().enumerate() {
+            let b = face[(i + 1) % face.len()];
+            if seen.insert((a.min(b), a.max(b))) {
+                r.extend([a as u32, b as u32]);
+            }
+        }
+    }
+    r
+}
+
+/// The area-weighted average of the normals of the faces around every
+/// vertex. Vertices at the same position share one normal, so that copies
+/// made for texture seams are lit alike.
+fn smooth_normals(positions: &[[f32; 3]], faces: &[Vec<usize>]) -> Vec<[f32; 3]> {
+    let key = |v: usize| positions[v].map(f32::to_bits);
+    let mut sums: HashMap<[u32; 3], [f32; 3]> = HashMap::new();
+    for face in faces {
+        // twice the area of the fan's triangles, so larger faces count for more
+        let a = positions[face[0]];
+        let mut normal = [0.0, 0.0, 0.0];
+        for i in 1..face.len() - 1 {
+            let b = positions[face[i]];
+            let c = positions[face[i + 1]];
+            normal = vec3_add(normal, vec3_cross(vec3_sub(b, a), vec3_sub(c, a)));
+        }
+        for &v in face {
+            let sum = sums.entry(key(v)).or_insert([0.0; 3]);
+            *sum = vec3_add(*sum, normal);
+        }
+    }
+
+    (0..positions.len())
+        .map(|v| {
+            let sum = sums.get(&key(v)).copied().unwrap_or([0.0; 3]);
+            let length = vec3_len(sum);
+            if length > 0.0 {
+                vec3_scale(sum, 1.0 / length)
+            } else {
+                [0.0, 0.0, 0.0]
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,62 @@
+use std::fmt::Display;
+
+/// `s` as a quoted JSON string.
+pub fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// `x` as a JSON number, or `null` for infinities and NaN, which JSON has no
+/// way to write.
+pub fn number<T: Display + Copy + Into<f64>>(x: T) -> String {
+    if x.into().is_finite() {
+        x.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+/// A JSON array of values that are already written.
+pub fn array<I: IntoIterator<Item = String>>(values: I) -> String {
+    format!("[{}]", values.into_iter().collect::<Vec<_>>().join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings_are_escaped() {
+        assert_eq!(string("a \"b\"\\c"), r#""a \"b\"\\c""#);
+        assert_eq!(string("line\nbreak\u{1}"), r#""line\nbreak\u0001""#);
+        assert_eq!(string("héllo"), "\"héllo\"");
+    }
+
+    #[test]
+    fn non_finite_numbers_are_null() {
+        assert_eq!(number(1.5f32), "1.5");
+        assert_eq!(number(-2.0f64), "-2");
+        assert_eq!(number(f32::NAN), "null");
+        assert_eq!(number(f64::INFINITY), "null");
+        assert_eq!(number(f32::NEG_INFINITY), "null");
+    }
+
+    #[test]
+    fn arrays() {
+        assert_eq!(array(Vec::new()), "[]");
+        assert_eq!(array([0.5f32, f32::NAN].map(number)), "[0.5,null]");
+    }
+}
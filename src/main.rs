@@ -1,28 +1,94 @@
+mod analysis;
+mod batch;
 mod camera;
+mod cli;
+mod compare;
+mod eigen;
+mod frame;
+mod json;
+mod mesh;
+mod parallel;
+mod ptex;
 mod quad_subdivide;
 mod render;
+mod repair;
+mod scheme;
 mod shaders;
+mod stats;
+mod stencil;
 mod support;
+mod topology;
 mod triangle_subdivide;
+mod validate;
+mod worker;
 
-use std::env;
+use clap::CommandFactory;
+use cli::{Cli, Command, ViewArgs};
+use mesh::Mesh;
+use scheme::Scheme;
+use std::error::Error;
+use std::process::ExitCode;
 
-fn main() {
-    let args: Vec<_> = env::args().collect();
-    if args.contains(&"-h".to_string()) || args.len() == 1 {
-        println!("Usage: subdivision [OPTIONS] [FILE]");
-        println!("");
-        println!("Arguments:");
-        println!("  [FILE]  .obj file to load");
-        println!("");
-        println!("Options:");
-        println!("  -c  Keep sharp creases");
-        println!("  -q  Load quads instead of triangles");
+fn view(args: &ViewArgs) -> Result<(), Box<dyn Error>> {
+    let path = match &args.file {
+        Some(path) => path,
+        None => {
+            Cli::command().print_help()?;
+            return Ok(());
+        }
+    };
+    let reload = {
+        let path = path.clone();
+        let scheme = args.subdivision.scheme;
+        let repair = args.repair.options(mesh::DEFAULT_WELD_TOLERANCE);
+        let force = args.force;
+        move || -> Result<(Mesh, Scheme), Box<dyn Error>> {
+            let mut base = mesh::load(&path)?;
+            repair::check(&mut base, repair);
+            validate::check(&base, force)?;
+            let scheme = scheme.unwrap_or_else(|| Scheme::detect(&base));
+            Ok((base, scheme))
+        }
+    };
+
+    let (base, scheme) = reload()?;
+    let textures = ptex::load(args.textures.source(), &base, scheme)?;
+    let watch = args.watch.then(|| Box::new(reload) as render::Reload);
+    render::render(
+        path.to_string(),
+        base,
+        scheme,
+        args.subdivision.creases,
+        args.subdivision.crease_cosine(),
+        textures,
+        watch,
+    );
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse_args();
+    if let Some(threads) = cli.threads {
+        parallel::set_threads(threads as usize);
     }
-    else {
-        let path = args.iter().find(|x| x.contains(".obj")).expect(".obj file expected");
-        let creases = args.contains(&"-c".to_string());
-        let quads = args.contains(&"-q".to_string());
-        render::render(path.to_string(), quads, creases);
+    let result = match &cli.command {
+        None => view(&cli.view),
+        Some(Command::View(args)) => view(args),
+        Some(Command::Subdivide(args)) => batch::subdivide(args),
+        Some(Command::Convert(args)) => batch::convert(args),
+        Some(Command::Stats(args)) => batch::stats(args),
+        Some(Command::Validate(args)) => batch::validate(args),
+        Some(Command::Compare(args)) => batch::compare(args),
+        Some(Command::Deform(args)) => batch::deform(args),
+        Some(Command::Matrix(args)) => batch::matrix(args),
+        Some(Command::Analyze(args)) => batch::analyze(args),
+        Some(Command::Trace(args)) => batch::trace(args),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
     }
 }
@@ -0,0 +1,271 @@
+use crate::json;
+use crate::mesh::Mesh;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+/// How `write_gltf` lays out the asset and which optional vertex
+/// attributes it emits.
+#[derive(Debug, Clone, Copy)]
+pub struct GltfOptions {
+    /// Write a single binary `.glb` instead of a `.gltf` with its buffer in
+    /// a `.bin` next to it.
+    pub binary: bool,
+    /// Write `NORMAL`, computed from the faces if the mesh has none.
+    pub normals: bool,
+    /// Write `TEXCOORD_0`, when the mesh has texture coordinates.
+    pub uvs: bool,
+}
+
+impl Default for GltfOptions {
+    fn default() -> Self {
+        GltfOptions {
+            binary: false,
+            normals: true,
+            uvs: true,
+        }
+    }
+}
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+
+/// Collects the binary buffer and the JSON for its views and accessors.
+#[derive(Default)]
+struct Builder {
+    bin: Vec<u8>,
+    views: Vec<String>,
+    accessors: Vec<String>,
+}
+
+impl Builder {
+    fn push_floats<const N: usize>(&mut self, data: &[[f32; N]], bounds: bool) -> usize {
+        let offset = self.bin.len();
+        for v in data {
+            for x in v {
+                self.bin.extend_from_slice(&x.to_le_bytes());
+            }
+        }
+        self.views.push(format!(
+            r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{}}}"#,
+            offset,
+            self.bin.len() - offset,
+            ARRAY_BUFFER
+        ));
+
+        let ty = match N {
+            2 => "VEC2",
+            3 => "VEC3",
+            _ => "SCALAR",
+        };
+        let mut accessor = format!(
+            r#"{{"bufferView":{},"componentType":{},"count":{},"type":"{}""#,
+            self.views.len() - 1,
+            FLOAT,
+            data.len(),
+            ty
+        );
+        if bounds && !data.is_empty() {
+            let mut min = data[0];
+            let mut max = data[0];
+            for v in data {
+                for k in 0..N {
+                    min[k] = min[k].min(v[k]);
+                    max[k] = max[k].max(v[k]);
+                }
+            }
+            accessor += &format!(
+                r#","min":{},"max":{}"#,
+                json::array(min.map(json::number)),
+                json::array(max.map(json::number))
+            );
+        }
+        accessor += "}";
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn push_indices(&mut self, indices: &[u32]) -> usize {
+        let offset = self.bin.len();
+        for i in indices {
+            self.bin.extend_from_slice(&i.to_le_bytes());
+        }
+        self.views.push(format!(
+            r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{}}}"#,
+            offset,
+            self.bin.len() - offset,
+            ELEMENT_ARRAY_BUFFER
+        ));
+        self.accessors.push(format!(
+            r#"{{"bufferView":{},"componentType":{},"count":{},"type":"SCALAR"}}"#,
+            self.views.len() - 1,
+            UNSIGNED_INT,
+            indices.len()
+        ));
+        self.accessors.len() - 1
+    }
+}
+
+/// Writes each mesh as its own glTF mesh and node, named `level 0`,
+/// `level 1`, ... so a whole chain of subdivision levels ends up in one
+/// asset.
+pub fn write_gltf(meshes: &[Mesh], path: &str, options: &GltfOptions) -> io::Result<()> {
+    let mut builder = Builder::default();
+    let mut gltf_meshes = Vec::new();
+    let mut nodes = Vec::new();
+
+    for (level, mesh) in meshes.iter().enumerate() {
+        let position = builder.push_floats(&mesh.positions, true);
+        let mut attributes = format!(r#""POSITION":{}"#, position);
+        if options.normals {
+            let normals = mesh
+                .normals
+                .clone()
+                .unwrap_or_else(|| mesh.vertex_normals());
+            let normal = builder.push_floats(&normals, false);
+            attributes += &format!(r#","NORMAL":{}"#, normal);
+        }
+        if let Some(uvs) = mesh.uvs.as_ref().filter(|_| options.uvs) {
+            // glTF puts the texture origin in the top left corner
+            let uvs: Vec<[f32; 2]> = uvs.iter().map(|t| [t[0], 1.0 - t[1]]).collect();
+            let uv = builder.push_floats(&uvs, false);
+            attributes += &format!(r#","TEXCOORD_0":{}"#, uv);
+        }
+
+        let mut indices = Vec::new();
+        for face in &mesh.faces {
+            for i in 1..face.len().saturating_sub(1) {
+                indices.extend([face[0] as u32, face[i] as u32, face[i + 1] as u32]);
+            }
+        }
+        let indices = builder.push_indices(&indices);
+
+        let name = json::string(&format!("level {}", level));
+        gltf_meshes.push(format!(
+            r#"{{"name":{},"primitives":[{{"attributes":{{{}}},"indices":{},"mode":4}}]}}"#,
+            name, attributes, indices
+        ));
+        nodes.push(format!(r#"{{"name":{},"mesh":{}}}"#, name, level));
+    }
+
+    let binary = options.binary;
+    let uri = if binary {
+        String::new()
+    } else {
+        let bin_path = Path::new(path).with_extension("bin");
+        let name = bin_path.file_name().unwrap().to_string_lossy().to_string();
+        File::create(&bin_path)?.write_all(&builder.bin)?;
+        format!(r#","uri":{}"#, json::string(&name))
+    };
+
+    let scene_nodes: Vec<String> = (0..nodes.len()).map(|i| i.to_string()).collect();
+    let json = format!(
+        concat!(
+            r#"{{"asset":{{"version":"2.0","generator":"subdivision"}},"#,
+            r#""scene":0,"scenes":[{{"nodes":[{}]}}],"nodes":[{}],"meshes":[{}],"#,
+            r#""buffers":[{{"byteLength":{}{}}}],"bufferViews":[{}],"accessors":[{}]}}"#
+        ),
+        scene_nodes.join(","),
+        nodes.join(","),
+        gltf_meshes.join(","),
+        builder.bin.len(),
+        uri,
+        builder.views.join(","),
+        builder.accessors.join(",")
+    );
+
+    let mut out = BufWriter::new(File::create(path)?);
+    if binary {
+        let mut json = json.into_bytes();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+        let mut bin = builder.bin;
+        while bin.len() % 4 != 0 {
+            bin.push(0);
+        }
+        let length = 12 + 8 + json.len() + 8 + bin.len();
+
+        out.write_all(b"glTF")?;
+        out.write_all(&2u32.to_le_bytes())?;
+        out.write_all(&(length as u32).to_le_bytes())?;
+        out.write_all(&(json.len() as u32).to_le_bytes())?;
+        out.write_all(b"JSON")?;
+        out.write_all(&json)?;
+        out.write_all(&(bin.len() as u32).to_le_bytes())?;
+        out.write_all(b"BIN\0")?;
+        out.write_all(&bin)?;
+    } else {
+        out.write_all(json.as_bytes())?;
+    }
+
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::{self, temp_path};
+    use crate::scheme::{self, Scheme};
+
+    fn u32_at(bytes: &[u8], at: usize) -> usize {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()) as usize
+    }
+
+    #[test]
+    fn glb_with_two_levels() {
+        let cube = mesh::load("assets/quad_cube.obj").unwrap();
+        let levels = [
+            cube.clone(),
+            scheme::subdivide(Scheme::CatmullClark, &cube, 0, 1, None),
+        ];
+        // the format comes from the options, not the extension
+        let path = temp_path("levels.gltf");
+        let options = GltfOptions {
+            binary: true,
+            ..GltfOptions::default()
+        };
+        write_gltf(&levels, &path, &options).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(&bytes[..4], b"glTF");
+        assert_eq!(u32_at(&bytes, 4), 2);
+        assert_eq!(u32_at(&bytes, 8), bytes.len());
+        let json_length = u32_at(&bytes, 12);
+        assert_eq!(&bytes[16..20], b"JSON");
+        assert_eq!(json_length % 4, 0);
+        let bin = 20 + json_length;
+        assert_eq!(&bytes[bin + 4..bin + 8], b"BIN\0");
+        assert_eq!(bin + 8 + u32_at(&bytes, bin), bytes.len());
+
+        // position, normal and index accessors for each level
+        let json = std::str::from_utf8(&bytes[20..bin]).unwrap();
+        let counts: Vec<usize> = json
+            .split(r#""count":"#)
+            .skip(1)
+            .map(|s| s.split(',').next().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(counts, [8, 8, 36, 26, 26, 144]);
+        assert!(json.contains(r#"{"name":"level 1","mesh":1}"#));
+    }
+
+    #[test]
+    fn gltf_keeps_its_buffer_next_to_it() {
+        let cube = mesh::load("assets/quad_cube.obj").unwrap();
+        let path = temp_path("cube.glb");
+        write_gltf(&[cube], &path, &GltfOptions::default()).unwrap();
+        let json = std::fs::read_to_string(&path).unwrap();
+        let bin = Path::new(&path).with_extension("bin");
+        let length = std::fs::metadata(&bin).unwrap().len();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&bin).unwrap();
+
+        assert!(json.starts_with('{'));
+        assert!(json.contains(&format!(r#""byteLength":{},"uri":"#, length)));
+    }
+}
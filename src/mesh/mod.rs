@@ -0,0 +1,619 @@
+mod gltf;
+mod obj;
+mod off;
+mod ply;
+mod stl;
+mod usd;
+
+pub use gltf::{write_gltf, GltfOptions};
+pub use obj::{load_wavefront, write_wavefront, ObjOptions};
+pub use off::{load_off, write_off};
+pub use ply::{load_ply, write_ply, PlyFormat};
+pub use stl::{load_stl, write_stl};
+pub use usd::write_usda;
+
+use crate::validate;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use vecmath::*;
+
+/// An indexed polygon mesh as it is stored on disk. Faces may have any number
+/// of vertices; the subdividers split them into triangles or quads.
+#[derive(Debug, Clone, Default)]
+pub struct Mesh {
+    pub positions: Vec<[f32; 3]>,
+    pub faces: Vec<Vec<usize>>,
+    /// Per-vertex normals, if the file had them.
+    pub normals: Option<Vec<[f32; 3]>>,
+    /// Per-vertex texture coordinates, if the file had them. Where a vertex is
+    /// given several coordinates (a UV seam) the first one wins.
+    pub uvs: Option<Vec<[f32; 2]>>,
+    /// Per-vertex colours in the 0..1 range.
+    pub colors: Option<Vec<[f32; 3]>>,
+    /// Any other per-vertex scalars the file carried, such as scanner
+    /// confidence or intensity.
+    pub properties: Vec<Property>,
+    pub groups: Vec<String>,
+    /// Index into `groups` for every face, empty when there are no groups.
+    pub face_groups: Vec<usize>,
+    /// Where every face lies in the mesh it was subdivided from, empty for
+    /// meshes that were not made by subdividing.
+    pub origins: Vec<Origin>,
+}
+
+/// A named per-vertex scalar attribute.
+#[derive(Debug, Clone)]
+pub struct Property {
+    pub name: String,
+    pub values: Vec<f32>,
+}
+
+/// How close two STL corners have to be to be welded into one vertex.
+pub const DEFAULT_WELD_TOLERANCE: f32 = 1e-4;
+
+/// By default neighbouring faces whose normals have a dot product below this
+/// (an angle of about 53 degrees) are treated as meeting at a sharp crease.
+pub const CREASE_COSINE: f32 = 0.6;
+
+/// The edges and vertices that crease detection keeps sharp.
+#[derive(Debug, Clone, Default)]
+pub struct Creases {
+    /// Edges between two faces that meet at a sharp angle.
+    pub edges: Vec<[usize; 2]>,
+    /// Vertices that are held in place because two of their faces meet at a
+    /// sharp angle.
+    pub corners: Vec<usize>,
+}
+
+/// Where a face of a subdivided mesh came from: the base face it lies in, and
+/// for each of its corners the weights over that base face's corners.
+///
+/// Like Ptex face ids, `face` and `piece` name the triangle or quad the
+/// subdividers start from, and `path` says which child was taken at each
+/// level below it.
+#[derive(Debug, Clone)]
+pub struct Origin {
+    pub face: usize,
+    /// Which of the triangles or quads the base face was split into: the
+    /// triangle of the fan for Loop, the quad at each corner for
+    /// Catmull-Clark, and 0 for faces that are already triangles or quads.
+    pub piece: usize,
+    /// The child taken at each level, 0 to 3 in the order the dividers push
+    /// them.
+    pub path: Vec<u8>,
+    pub corners: Vec<Vec<f32>>,
+}
+
+impl Origin {
+    /// The origin of a face that is a sub-polygon of base face `face`, with
+    /// `corners` listing the base face corners each of its corners sits on.
+    pub fn of_corners(face: usize, piece: usize, n: usize, corners: &[usize]) -> Origin {
+        Origin {
+            face,
+            piece,
+            path: Vec::new(),
+            corners: corners
+                .iter()
+                .map(|&c| {
+                    let mut w = vec![0.0; n];
+                    w[c] = 1.0;
+                    w
+                })
+                .collect(),
+        }
+    }
+
+    /// The origin of child `index` of this face, whose corners are averages
+    /// of this face's corners as described by
+    /// `weights[child corner][parent corner]`.
+    pub fn child<const N: usize>(&self, index: u8, weights: &[[f32; N]; N]) -> Origin {
+        let mut path = self.path.clone();
+        path.push(index);
+        Origin {
+            face: self.face,
+            piece: self.piece,
+            path,
+            corners: weights
+                .iter()
+                .map(|row| {
+                    let mut w = vec![0.0; self.corners[0].len()];
+                    for (parent, &x) in self.corners.iter().zip(row.iter()) {
+                        for (wi, pi) in w.iter_mut().zip(parent.iter()) {
+                            *wi += x * pi;
+                        }
+                    }
+                    w
+                })
+                .collect(),
+        }
+    }
+
+    /// How many subdivision steps the face is below its base face.
+    pub fn level(&self) -> u32 {
+        self.path.len() as u32
+    }
+}
+
+impl Mesh {
+    pub fn is_triangles(&self) -> bool {
+        self.faces.iter().all(|f| f.len() == 3)
+    }
+
+    /// Builds the mesh produced by subdividing `base`. `faces` index into
+    /// `positions` and `origins[i]` says where face `i` lies in the base mesh,
+    /// which is used to carry texture coordinates and groups over.
+    pub fn refined(
+        base: &Mesh,
+        positions: Vec<[f32; 3]>,
+        faces: Vec<Vec<usize>>,
+        origins: &[Origin],
+    ) -> Mesh {
+        let carry = |values: &[f32], stride: usize| {
+            let mut r = vec![0.0; positions.len() * stride];
+            let mut seen = vec![false; positions.len()];
+            for (face, origin) in faces.iter().zip(origins) {
+                let base_face = &base.faces[origin.face];
+                for (&v, weights) in face.iter().zip(&origin.corners) {
+                    if seen[v] {
+                        continue;
+                    }
+                    seen[v] = true;
+                    for (&b, &w) in base_face.iter().zip(weights) {
+                        for k in 0..stride {
+                            r[v * stride + k] += w * values[b * stride + k];
+                        }
+                    }
+                }
+            }
+            r
+        };
+
+        let uvs = base.uvs.as_ref().map(|uvs| {
+            carry(uvs.concat().as_slice(), 2)
+                .chunks(2)
+                .map(|t| [t[0], t[1]])
+                .collect()
+        });
+        let colors = base.colors.as_ref().map(|colors| {
+            carry(colors.concat().as_slice(), 3)
+                .chunks(3)
+                .map(|c| [c[0], c[1], c[2]])
+                .collect()
+        });
+        let properties = base
+            .properties
+            .iter()
+            .map(|p| Property {
+                name: p.name.clone(),
+                values: carry(&p.values, 1),
+            })
+            .collect();
+
+        let face_groups = if base.face_groups.is_empty() {
+            Vec::new()
+        } else {
+            origins.iter().map(|o| base.face_groups[o.face]).collect()
+        };
+
+        Mesh {
+            positions,
+            faces,
+            normals: None,
+            uvs,
+            colors,
+            properties,
+            groups: base.groups.clone(),
+            face_groups,
+            origins: origins.to_vec(),
+        }
+    }
+
+    /// Keys for `weld_apart` that keep each vertex apart from the others
+    /// within `tolerance` of it only where welding them would join separate
+    /// fans of faces, as with the copies `repair` makes of a pinched vertex.
+    /// Other vertices get `None` and weld as usual, closing seams.
+    pub fn kept_apart(&self, tolerance: f32) -> Vec<Option<usize>> {
+        let (remap, kept) = weld_positions(&self.positions, tolerance);
+        let welded = Mesh {
+            faces: self
+                .faces
+                .iter()
+                .map(|face| face.iter().map(|&v| remap[v]).collect())
+                .collect(),
+            ..Mesh::default()
+        };
+        let mut vertex_faces = vec![Vec::new(); kept.len()];
+        for (f, face) in welded.faces.iter().enumerate() {
+            for &v in face {
+                if vertex_faces[v].last() != Some(&f) {
+                    vertex_faces[v].push(f);
+                }
+            }
+        }
+        let pinched: Vec<bool> = vertex_faces
+            .iter()
+            .enumerate()
+            .map(|(v, faces)| validate::fans(&welded, v, faces).len() > 1)
+            .collect();
+        (0..self.positions.len())
+            .map(|i| pinched[remap[i]].then_some(i))
+            .collect()
+    }
+
+    /// Merges vertices that are closer than `tolerance`, then drops the faces
+    /// that collapsed or became duplicates. Vertex attributes are taken from
+    /// the first vertex of each merged cluster. A tolerance of zero merges
+    /// only vertices at exactly the same position.
+    pub fn weld(&mut self, tolerance: f32) {
+        let (remap, kept) = weld_positions(&self.positions, tolerance);
+
+        self.positions = kept.iter().map(|&i| self.positions[i]).collect();
+        if let Some(normals) = &mut self.normals {
+            *normals = kept.iter().map(|&i| normals[i]).collect();
+        }
+        if let Some(uvs) = &mut self.uvs {
+            *uvs = kept.iter().map(|&i| uvs[i]).collect();
+        }
+        if let Some(colors) = &mut self.colors {
+            *colors = kept.iter().map(|&i| colors[i]).collect();
+        }
+        for property in &mut self.properties {
+            property.values = kept.iter().map(|&i| property.values[i]).collect();
+        }
+
+        let mut seen = HashMap::new();
+        let mut faces = Vec::new();
+        let mut face_groups = Vec::new();
+        let mut origins = Vec::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            let mut face: Vec<usize> = face.iter().map(|&v| remap[v]).collect();
+            face.dedup();
+            while face.len() > 1 && face.first() == face.last() {
+                face.pop();
+            }
+            if face.len() < 3 {
+                continue;
+            }
+            let mut key = face.clone();
+            key.sort_unstable();
+            if seen.insert(key, f).is_some() {
+                continue;
+            }
+            faces.push(face);
+            if !self.face_groups.is_empty() {
+                face_groups.push(self.face_groups[f]);
+            }
+            if !self.origins.is_empty() {
+                origins.push(self.origins[f].clone());
+            }
+        }
+        self.faces = faces;
+        self.face_groups = face_groups;
+        self.origins = origins;
+    }
+
+    /// Finds the creases the same way the subdividers do, from the angles
+    /// between face normals.
+    pub fn creases(&self, cosine: f32) -> Creases {
+        let normals: Vec<[f32; 3]> = self
+            .faces
+            .iter()
+            .map(|f| vec3_normalized(face_normal(&self.positions, f)))
+            .collect();
+
+        let mut edges: HashMap<[usize; 2], Vec<usize>> = HashMap::new();
+        let mut vertex_faces: Vec<Vec<usize>> = vec![Vec::new(); self.positions.len()];
+        for (f, face) in self.faces.iter().enumerate() {
+            for i in 0..face.len() {
+                let a = face[i];
+                let b = face[(i + 1) % face.len()];
+                edges.entry([a.min(b), a.max(b)]).or_default().push(f);
+                vertex_faces[a].push(f);
+            }
+        }
+
+        let mut creases = Creases::default();
+        for (edge, faces) in edges {
+            if faces.len() == 2 && vec3_dot(normals[faces[0]], normals[faces[1]]) < cosine {
+                creases.edges.push(edge);
+            }
+        }
+        creases.edges.sort_unstable();
+
+        for (v, faces) in vertex_faces.iter().enumerate() {
+            let sharp = faces.iter().any(|&a| {
+                faces
+                    .iter()
+                    .any(|&b| vec3_dot(normals[a], normals[b]) < cosine)
+            });
+            if sharp {
+                creases.corners.push(v);
+            }
+        }
+
+        creases
+    }
+
+    /// Area weighted vertex normals computed from the faces.
+    pub fn vertex_normals(&self) -> Vec<[f32; 3]> {
+        let mut normals = vec![[0.0, 0.0, 0.0]; self.positions.len()];
+        for face in &self.faces {
+            let n = face_normal(&self.positions, face);
+            for &v in face {
+                normals[v] = vec3_add(normals[v], n);
+            }
+        }
+        for n in normals.iter_mut() {
+            if vec3_len(*n) > 0.0 {
+                *n = vec3_normalized(*n);
+            }
+        }
+        normals
+    }
+}
+
+/// The file formats meshes can be written as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Obj,
+    /// Binary little endian PLY
+    Ply,
+    PlyAscii,
+    Stl,
+    Off,
+    Gltf,
+    Glb,
+    /// Control cage for renderers that subdivide natively
+    Usda,
+}
+
+impl Format {
+    pub fn from_path(path: &str) -> Option<Format> {
+        match extension(path).as_str() {
+            "obj" => Some(Format::Obj),
+            "ply" => Some(Format::Ply),
+            "stl" => Some(Format::Stl),
+            "off" => Some(Format::Off),
+            "gltf" => Some(Format::Gltf),
+            "glb" => Some(Format::Glb),
+            "usda" => Some(Format::Usda),
+            _ => None,
+        }
+    }
+}
+
+/// Loads a mesh, picking the reader from the file extension.
+pub fn load(path: &str) -> io::Result<Mesh> {
+    load_with(path, DEFAULT_WELD_TOLERANCE)
+}
+
+/// Like `load`, with the tolerance used to weld STL triangles together.
+pub fn load_with(path: &str, weld_tolerance: f32) -> io::Result<Mesh> {
+    match extension(path).as_str() {
+        "obj" => load_wavefront(path),
+        "ply" => load_ply(path),
+        "stl" => load_stl(path, weld_tolerance),
+        "off" => load_off(path),
+        _ => Err(unsupported(path)),
+    }
+}
+
+/// Saves a mesh, picking the writer from the file extension.
+pub fn save(mesh: &Mesh, path: &str) -> io::Result<()> {
+    match Format::from_path(path) {
+        Some(format) => save_as(mesh, path, format),
+        None => Err(unsupported(path)),
+    }
+}
+
+/// Saves a mesh in the given format. USD needs the subdivision scheme, so
+/// `.usda` files are written with `write_usda` instead.
+pub fn save_as(mesh: &Mesh, path: &str, format: Format) -> io::Result<()> {
+    match format {
+        Format::Obj => write_wavefront(
+            mesh,
+            path,
+            &ObjOptions {
+                normals: true,
+                uvs: true,
+                groups: true,
+            },
+        ),
+        Format::Ply => write_ply(mesh, path, PlyFormat::BinaryLittleEndian),
+        Format::PlyAscii => write_ply(mesh, path, PlyFormat::Ascii),
+        Format::Stl => write_stl(mesh, path),
+        Format::Off => write_off(mesh, path),
+        Format::Gltf | Format::Glb => write_gltf(
+            std::slice::from_ref(mesh),
+            path,
+            &GltfOptions {
+                binary: format == Format::Glb,
+                ..GltfOptions::default()
+            },
+        ),
+        Format::Usda => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "USD output needs the subdivision scheme, use write_usda",
+        )),
+    }
+}
+
+/// Whether `load` can read the file.
+pub fn is_supported(path: &str) -> bool {
+    matches!(extension(path).as_str(), "obj" | "ply" | "stl" | "off")
+}
+
+fn extension(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn unsupported(path: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{}: unsupported file format", path),
+    )
+}
+
+/// Groups positions that are within `tolerance` of an earlier one. Returns
+/// the group of every position and the first position of every group, with
+/// groups numbered in the order they first appear.
+pub fn weld_positions(positions: &[[f32; 3]], tolerance: f32) -> (Vec<usize>, Vec<usize>) {
+    let cell = |p: [f32; 3]| {
+        [
+            (p[0] / tolerance).floor() as i64,
+            (p[1] / tolerance).floor() as i64,
+            (p[2] / tolerance).floor() as i64,
+        ]
+    };
+
+    let mut grid: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+    let mut remap = vec![0; positions.len()];
+    let mut kept = Vec::new();
+    for (i, &p) in positions.iter().enumerate() {
+        let c = cell(p);
+        let mut found = None;
+        'search: for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    if let Some(candidates) = grid.get(&[
+                        c[0].saturating_add(dx),
+                        c[1].saturating_add(dy),
+                        c[2].saturating_add(dz),
+                    ]) {
+                        for &k in candidates {
+                            if vec3_len(vec3_sub(positions[kept[k]], p)) <= tolerance {
+                                found = Some(k);
+                                break 'search;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        remap[i] = found.unwrap_or_else(|| {
+            kept.push(i);
+            grid.entry(c).or_default().push(kept.len() - 1);
+            kept.len() - 1
+        });
+    }
+    (remap, kept)
+}
+
+/// Like `weld_positions`, but positions with different `apart` keys are never
+/// welded together, so copies of a vertex at one position stay separate.
+pub fn weld_apart(
+    positions: &[[f32; 3]],
+    apart: &[Option<usize>],
+    tolerance: f32,
+) -> (Vec<usize>, Vec<usize>) {
+    let (groups, _) = weld_positions(positions, tolerance);
+    let mut ids = HashMap::new();
+    let mut remap = Vec::with_capacity(positions.len());
+    let mut kept = Vec::new();
+    for (i, &group) in groups.iter().enumerate() {
+        let id = *ids.entry((group, apart[i])).or_insert_with(|| {
+            kept.push(i);
+            kept.len() - 1
+        });
+        remap.push(id);
+    }
+    (remap, kept)
+}
+
+/// Newell's method, the length of the result is twice the face area. The
+/// corners are taken relative to the first one, so that faces far from the
+/// origin keep their precision.
+pub fn face_normal(positions: &[[f32; 3]], face: &[usize]) -> [f32; 3] {
+    let origin = positions[face[0]];
+    let mut n = [0.0, 0.0, 0.0];
+    for i in 1..face.len().saturating_sub(1) {
+        let a = vec3_sub(positions[face[i]], origin);
+        let b = vec3_sub(positions[face[i + 1]], origin);
+        n = vec3_add(n, vec3_cross(a, b));
+    }
+    n
+}
+
+/// A path in the temporary directory for a test to write `name` to.
+#[cfg(test)]
+pub fn temp_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!("subdivision-{}-{}", std::process::id(), name))
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangles(positions: Vec<[f32; 3]>) -> Mesh {
+        Mesh {
+            faces: vec![vec![0, 1, 2], vec![3, 4, 5]],
+            positions,
+            ..Mesh::default()
+        }
+    }
+
+    #[test]
+    fn keeps_apart_only_copies_that_would_pinch() {
+        // a seam: the shared edge's corners are duplicated
+        let seam = triangles(vec![
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 0.0],
+        ]);
+        assert_eq!(seam.kept_apart(1e-4), vec![None; 6]);
+
+        // two triangles touching at one corner, which has a copy for each
+        let pinch = triangles(vec![
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0],
+            [-1.0, 0.0, 0.0],
+            [0.0, -1.0, 0.0],
+        ]);
+        assert_eq!(
+            pinch.kept_apart(1e-4),
+            vec![Some(0), None, None, Some(3), None, None]
+        );
+    }
+
+    #[test]
+    fn weld_merges_close_corners() {
+        let mut mesh = triangles(vec![
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 0.00001, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.00001, 1.0, 0.0],
+        ]);
+        mesh.weld(1e-4);
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.faces, vec![vec![0, 1, 2], vec![1, 3, 2]]);
+    }
+
+    #[test]
+    fn weld_with_zero_tolerance_merges_exact_duplicates() {
+        let mut mesh = triangles(vec![
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [-1.0, -1.0, 0.0],
+            [0.0, 1.00001, 0.0],
+        ]);
+        mesh.weld(0.0);
+        assert_eq!(mesh.positions.len(), 5);
+        assert_eq!(mesh.faces[1], vec![1, 3, 4]);
+    }
+}
@@ -0,0 +1,257 @@
+use crate::mesh::Mesh;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufWriter};
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Loads an OBJ file. Faces need at least three corners, and every vertex,
+/// texture coordinate and normal they use must exist.
+pub fn load_wavefront(path: &str) -> io::Result<Mesh> {
+    let mut file = File::open(path)?;
+    let mut bytes: Vec<u8> = Vec::new();
+    file.read_to_end(&mut bytes)?;
+
+    let mut data = ::std::io::BufReader::new(&bytes[..]);
+    let data = obj::ObjData::load_buf(&mut data).map_err(|e| invalid(e.to_string()))?;
+
+    // the faces come back in file order but without their line numbers
+    let text = String::from_utf8_lossy(&bytes);
+    let mut face_lines = text
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| l.split_whitespace().next() == Some("f"))
+        .map(|(i, _)| i + 1);
+
+    let mut faces = Vec::new();
+    let mut groups: Vec<String> = Vec::new();
+    let mut face_groups = Vec::new();
+    let mut uvs = vec![None; data.position.len()];
+    let mut normals = vec![None; data.position.len()];
+
+    for object in data.objects.iter() {
+        for group in object.groups.iter() {
+            let g = match groups.iter().position(|name| *name == group.name) {
+                Some(g) => g,
+                None => {
+                    groups.push(group.name.clone());
+                    groups.len() - 1
+                }
+            };
+            for obj::SimplePolygon(indices) in group.polys.iter() {
+                let line = face_lines.next().unwrap_or(0);
+                if indices.len() < 3 {
+                    return Err(invalid(format!(
+                        "line {}: face with fewer than three corners",
+                        line
+                    )));
+                }
+                for obj::IndexTuple(v, t, n) in indices {
+                    let missing = if *v >= data.position.len() {
+                        Some("vertex")
+                    } else if t.is_some_and(|t| t >= data.texture.len()) {
+                        Some("texture coordinate")
+                    } else if n.is_some_and(|n| n >= data.normal.len()) {
+                        Some("normal")
+                    } else {
+                        None
+                    };
+                    if let Some(missing) = missing {
+                        return Err(invalid(format!(
+                            "line {}: face uses a missing {}",
+                            line, missing
+                        )));
+                    }
+                    if let Some(t) = t {
+                        uvs[*v] = uvs[*v].or(Some(data.texture[*t]));
+                    }
+                    if let Some(n) = n {
+                        normals[*v] = normals[*v].or(Some(data.normal[*n]));
+                    }
+                }
+                faces.push(indices.iter().map(|i| i.0).collect());
+                face_groups.push(g);
+            }
+        }
+    }
+
+    let uvs = if uvs.iter().any(|t| t.is_some()) {
+        Some(uvs.into_iter().map(|t| t.unwrap_or([0.0, 0.0])).collect())
+    } else {
+        None
+    };
+    let normals = if normals.iter().any(|n| n.is_some()) {
+        Some(
+            normals
+                .into_iter()
+                .map(|n| n.unwrap_or([0.0, 0.0, 0.0]))
+                .collect(),
+        )
+    } else {
+        None
+    };
+
+    Ok(Mesh {
+        positions: data.position,
+        faces,
+        normals,
+        uvs,
+        groups,
+        face_groups,
+        ..Default::default()
+    })
+}
+
+/// Which optional parts of the mesh `write_wavefront` emits.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ObjOptions {
+    /// Write `vn` lines, computed from the faces if the mesh has none.
+    pub normals: bool,
+    /// Write `vt` lines, when the mesh has texture coordinates.
+    pub uvs: bool,
+    /// Write `g` lines, when the mesh has groups.
+    pub groups: bool,
+}
+
+pub fn write_wavefront(mesh: &Mesh, path: &str, options: &ObjOptions) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+
+    writeln!(
+        out,
+        "# {} vertices, {} faces",
+        mesh.positions.len(),
+        mesh.faces.len()
+    )?;
+    for p in &mesh.positions {
+        writeln!(out, "v {} {} {}", p[0], p[1], p[2])?;
+    }
+
+    let uvs = mesh.uvs.as_ref().filter(|_| options.uvs);
+    if let Some(uvs) = uvs {
+        for t in uvs {
+            writeln!(out, "vt {} {}", t[0], t[1])?;
+        }
+    }
+
+    let normals = if options.normals {
+        Some(
+            mesh.normals
+                .clone()
+                .unwrap_or_else(|| mesh.vertex_normals()),
+        )
+    } else {
+        None
+    };
+    if let Some(normals) = &normals {
+        for n in normals {
+            writeln!(out, "vn {} {} {}", n[0], n[1], n[2])?;
+        }
+    }
+
+    let mut group = None;
+    for (i, face) in mesh.faces.iter().enumerate() {
+        if options.groups && !mesh.face_groups.is_empty() && group != Some(mesh.face_groups[i]) {
+            group = Some(mesh.face_groups[i]);
+            writeln!(out, "g {}", mesh.groups[mesh.face_groups[i]])?;
+        }
+
+        write!(out, "f")?;
+        for &v in face {
+            let v = v + 1;
+            match (uvs.is_some(), normals.is_some()) {
+                (false, false) => write!(out, " {}", v)?,
+                (true, false) => write!(out, " {}/{}", v, v)?,
+                (false, true) => write!(out, " {}//{}", v, v)?,
+                (true, true) => write!(out, " {}/{}/{}", v, v, v)?,
+            }
+        }
+        writeln!(out)?;
+    }
+
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::temp_path;
+
+    fn load_text(name: &str, text: &str) -> io::Result<Mesh> {
+        let path = temp_path(name);
+        std::fs::write(&path, text).unwrap();
+        let mesh = load_wavefront(&path);
+        std::fs::remove_file(&path).unwrap();
+        mesh
+    }
+
+    #[test]
+    fn round_trip() {
+        // two quads sharing an edge, in two groups
+        let mesh = Mesh {
+            positions: vec![
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [2.0, 0.0, 0.0],
+                [2.0, 1.0, 0.0],
+            ],
+            faces: vec![vec![0, 1, 2, 3], vec![1, 4, 5, 2]],
+            uvs: Some(vec![
+                [0.0, 0.0],
+                [0.5, 0.0],
+                [0.5, 1.0],
+                [0.0, 1.0],
+                [1.0, 0.0],
+                [1.0, 1.0],
+            ]),
+            groups: vec!["left".to_string(), "right".to_string()],
+            face_groups: vec![0, 1],
+            ..Mesh::default()
+        };
+        let path = temp_path("round-trip.obj");
+        let options = ObjOptions {
+            normals: true,
+            uvs: true,
+            groups: true,
+        };
+        write_wavefront(&mesh, &path, &options).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        let read = load_wavefront(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(text.lines().filter(|l| l.starts_with("v ")).count(), 6);
+        assert_eq!(read.positions, mesh.positions);
+        assert_eq!(read.faces, mesh.faces);
+        assert_eq!(read.uvs, mesh.uvs);
+        assert_eq!(read.normals, Some(vec![[0.0, 0.0, 1.0]; 6]));
+        assert_eq!(read.groups, mesh.groups);
+        assert_eq!(read.face_groups, mesh.face_groups);
+    }
+
+    #[test]
+    fn rejects_faces_that_do_not_fit() {
+        let vertices = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvn 0 0 1\n";
+        assert!(load_text("fits.obj", &format!("{}f 1/1/1 2/1/1 -1/1/1\n", vertices)).is_ok());
+        for face in [
+            "f 1 2 9",
+            "f 1 2 -4",
+            "f 1 2",
+            "f 1/2 2/1 3/1",
+            "f 1//1 2//2 3//1",
+        ] {
+            let text = format!("{}# a comment\n{}\n", vertices, face);
+            let error = load_text("bad.obj", &text).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", face);
+            assert!(
+                error.to_string().starts_with("line 7:"),
+                "{}: {}",
+                face,
+                error
+            );
+        }
+    }
+}
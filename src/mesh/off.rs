@@ -0,0 +1,221 @@
+use crate::mesh::Mesh;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufWriter};
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Loads an OFF or COFF file. COFF vertex colours may be given as integers in
+/// 0..255 or floats in 0..1, any alpha is ignored. Faces can have any number
+/// of vertices; face colours are skipped.
+pub fn load_off(path: &str) -> io::Result<Mesh> {
+    let mut text = String::new();
+    File::open(path)?.read_to_string(&mut text)?;
+
+    let lines: Vec<Vec<&str>> = text
+        .lines()
+        .map(|l| {
+            l.split('#')
+                .next()
+                .unwrap()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+        })
+        .filter(|l| !l.is_empty())
+        .collect();
+    let mut lines = lines.into_iter();
+
+    let mut header = lines
+        .next()
+        .ok_or_else(|| invalid("empty OFF file".to_string()))?;
+    let keyword = header[0];
+    let colors = match keyword {
+        "OFF" => false,
+        "COFF" => true,
+        _ => return Err(invalid(format!("unsupported OFF header {}", keyword))),
+    };
+    header.remove(0);
+    if header.is_empty() {
+        header = lines
+            .next()
+            .ok_or_else(|| invalid("OFF file has no counts".to_string()))?;
+    }
+
+    let number = |word: &str| -> io::Result<f32> {
+        word.parse()
+            .map_err(|_| invalid(format!("bad OFF value {}", word)))
+    };
+    let index = |word: &str| -> io::Result<usize> {
+        word.parse()
+            .map_err(|_| invalid(format!("bad OFF index {}", word)))
+    };
+    let count = |i: usize| -> io::Result<usize> {
+        let word = header
+            .get(i)
+            .ok_or_else(|| invalid("OFF file has no counts".to_string()))?;
+        word.parse()
+            .map_err(|_| invalid(format!("bad OFF count {}", word)))
+    };
+    let vertex_count = count(0)?;
+    let face_count = count(1)?;
+
+    let mut mesh = Mesh::default();
+    let mut vertex_colors = Vec::new();
+    for _ in 0..vertex_count {
+        let line = lines
+            .next()
+            .ok_or_else(|| invalid("OFF file ends in the vertex list".to_string()))?;
+        if line.len() < 3 {
+            return Err(invalid(format!("bad OFF vertex {:?}", line)));
+        }
+        mesh.positions
+            .push([number(line[0])?, number(line[1])?, number(line[2])?]);
+
+        if colors {
+            if line.len() < 6 {
+                return Err(invalid(format!("COFF vertex without a colour {:?}", line)));
+            }
+            let integer = line[3..6].iter().all(|w| !w.contains('.'));
+            let scale = if integer { 1.0 / 255.0 } else { 1.0 };
+            vertex_colors.push([
+                number(line[3])? * scale,
+                number(line[4])? * scale,
+                number(line[5])? * scale,
+            ]);
+        }
+    }
+
+    for _ in 0..face_count {
+        let line = lines
+            .next()
+            .ok_or_else(|| invalid("OFF file ends in the face list".to_string()))?;
+        let n = index(line[0])?;
+        if n < 3 || line.len() < n + 1 {
+            return Err(invalid(format!("bad OFF face {:?}", line)));
+        }
+        let mut face = Vec::with_capacity(n);
+        for word in &line[1..n + 1] {
+            let v = index(word)?;
+            if v >= vertex_count {
+                return Err(invalid(format!("OFF face uses missing vertex {}", v)));
+            }
+            face.push(v);
+        }
+        mesh.faces.push(face);
+    }
+
+    if colors {
+        mesh.colors = Some(vertex_colors);
+    }
+
+    Ok(mesh)
+}
+
+/// Writes the mesh as COFF when it has vertex colours and plain OFF otherwise.
+pub fn write_off(mesh: &Mesh, path: &str) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+
+    let edges: std::collections::HashSet<[usize; 2]> = mesh
+        .faces
+        .iter()
+        .flat_map(|f| (0..f.len()).map(move |i| (f[i], f[(i + 1) % f.len()])))
+        .map(|(a, b)| [a.min(b), a.max(b)])
+        .collect();
+
+    writeln!(
+        out,
+        "{}",
+        if mesh.colors.is_some() { "COFF" } else { "OFF" }
+    )?;
+    writeln!(
+        out,
+        "{} {} {}",
+        mesh.positions.len(),
+        mesh.faces.len(),
+        edges.len()
+    )?;
+    for (i, p) in mesh.positions.iter().enumerate() {
+        write!(out, "{} {} {}", p[0], p[1], p[2])?;
+        if let Some(colors) = &mesh.colors {
+            let c = colors[i].map(|x| (x * 255.0).round().clamp(0.0, 255.0) as u8);
+            write!(out, " {} {} {} 255", c[0], c[1], c[2])?;
+        }
+        writeln!(out)?;
+    }
+    for face in &mesh.faces {
+        write!(out, "{}", face.len())?;
+        for v in face {
+            write!(out, " {}", v)?;
+        }
+        writeln!(out)?;
+    }
+
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::temp_path;
+
+    fn load_text(name: &str, text: &str) -> io::Result<Mesh> {
+        let path = temp_path(name);
+        std::fs::write(&path, text).unwrap();
+        let mesh = load_off(&path);
+        std::fs::remove_file(&path).unwrap();
+        mesh
+    }
+
+    #[test]
+    fn round_trip() {
+        let mesh = Mesh {
+            positions: vec![
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.5],
+                [0.0, 1.0, 0.0],
+                [0.5, 0.5, 1.0],
+            ],
+            faces: vec![vec![0, 1, 2, 3], vec![0, 1, 4]],
+            colors: Some(vec![
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 0.0, 1.0],
+                [1.0, 1.0, 1.0],
+                [0.0, 0.0, 0.0],
+            ]),
+            ..Mesh::default()
+        };
+        let path = temp_path("round-trip.off");
+        write_off(&mesh, &path).unwrap();
+        let read = load_off(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.positions, mesh.positions);
+        assert_eq!(read.faces, mesh.faces);
+        assert_eq!(read.colors, mesh.colors);
+    }
+
+    #[test]
+    fn comments_and_counts_on_their_own_line() {
+        let mesh = load_text(
+            "comments.off",
+            "OFF # a triangle\n3 1 0\n0 0 0\n1 0 0\n# the apex\n0 1 0\n3 0 1 2\n",
+        )
+        .unwrap();
+        assert_eq!(mesh.positions.len(), 3);
+        assert_eq!(mesh.faces, vec![vec![0, 1, 2]]);
+        assert!(mesh.colors.is_none());
+    }
+
+    #[test]
+    fn rejects_indices_that_are_not_integers() {
+        for face in ["3 0 1 1.9", "3 0 1 -1", "3.0 0 1 2", "3 0 1 3"] {
+            let text = format!("OFF\n3 1 0\n0 0 0\n1 0 0\n0 1 0\n{}\n", face);
+            assert!(load_text("bad.off", &text).is_err(), "{}", face);
+        }
+    }
+}
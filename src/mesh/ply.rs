@@ -0,0 +1,559 @@
+use crate::mesh::{Mesh, Property};
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufWriter};
+
+/// The encodings a PLY file body can use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> io::Result<Scalar> {
+        match name {
+            "char" | "int8" => Ok(Scalar::I8),
+            "uchar" | "uint8" => Ok(Scalar::U8),
+            "short" | "int16" => Ok(Scalar::I16),
+            "ushort" | "uint16" => Ok(Scalar::U16),
+            "int" | "int32" => Ok(Scalar::I32),
+            "uint" | "uint32" => Ok(Scalar::U32),
+            "float" | "float32" => Ok(Scalar::F32),
+            "double" | "float64" => Ok(Scalar::F64),
+            _ => Err(invalid(format!("unknown PLY type {}", name))),
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum PropertyType {
+    Scalar(Scalar),
+    List(Scalar, Scalar),
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<(String, PropertyType)>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads values one at a time from the body of a PLY file.
+struct Body<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    format: PlyFormat,
+}
+
+impl<'a> Body<'a> {
+    fn token(&mut self) -> io::Result<&'a str> {
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        let start = self.pos;
+        while self.pos < self.bytes.len() && !self.bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(invalid("unexpected end of PLY data".to_string()));
+        }
+        std::str::from_utf8(&self.bytes[start..self.pos])
+            .map_err(|_| invalid("PLY data is not valid text".to_string()))
+    }
+
+    fn read(&mut self, ty: Scalar) -> io::Result<f64> {
+        if self.format == PlyFormat::Ascii {
+            let token = self.token()?;
+            return token
+                .parse()
+                .map_err(|_| invalid(format!("bad PLY value {}", token)));
+        }
+
+        let size = ty.size();
+        if self.pos + size > self.bytes.len() {
+            return Err(invalid("unexpected end of PLY data".to_string()));
+        }
+        let mut b = [0u8; 8];
+        b[..size].copy_from_slice(&self.bytes[self.pos..self.pos + size]);
+        self.pos += size;
+        if self.format == PlyFormat::BinaryBigEndian {
+            b[..size].reverse();
+        }
+
+        Ok(match ty {
+            Scalar::I8 => b[0] as i8 as f64,
+            Scalar::U8 => b[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes(b),
+        })
+    }
+}
+
+fn parse_header(text: &str) -> io::Result<(PlyFormat, Vec<Element>)> {
+    let mut lines = text.lines();
+    if lines.next().map(|l| l.trim()) != Some("ply") {
+        return Err(invalid("not a PLY file".to_string()));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", "ascii", _] => format = Some(PlyFormat::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(PlyFormat::BinaryLittleEndian),
+            ["format", "binary_big_endian", _] => format = Some(PlyFormat::BinaryBigEndian),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| invalid(format!("bad element count {}", count)))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => elements
+                .last_mut()
+                .ok_or_else(|| invalid("property before element".to_string()))?
+                .properties
+                .push((
+                    name.to_string(),
+                    PropertyType::List(Scalar::parse(count)?, Scalar::parse(item)?),
+                )),
+            ["property", ty, name] => elements
+                .last_mut()
+                .ok_or_else(|| invalid("property before element".to_string()))?
+                .properties
+                .push((name.to_string(), PropertyType::Scalar(Scalar::parse(ty)?))),
+            ["end_header"] => break,
+            _ => (),
+        }
+    }
+
+    let format = format.ok_or_else(|| invalid("PLY header has no format".to_string()))?;
+    Ok((format, elements))
+}
+
+pub fn load_ply(path: &str) -> io::Result<Mesh> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+
+    let marker = b"end_header";
+    let end = bytes
+        .windows(marker.len())
+        .position(|w| w == marker)
+        .ok_or_else(|| invalid("PLY header has no end_header".to_string()))?;
+    let mut body_start = end + marker.len();
+    if bytes.get(body_start) == Some(&b'\r') {
+        body_start += 1;
+    }
+    body_start += 1;
+
+    let header = String::from_utf8_lossy(&bytes[..end + marker.len()]);
+    let (format, elements) = parse_header(&header)?;
+
+    let mut body = Body {
+        bytes: &bytes[body_start.min(bytes.len())..],
+        pos: 0,
+        format,
+    };
+
+    let mut mesh = Mesh::default();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut colors = Vec::new();
+
+    for element in &elements {
+        let is_vertex = element.name == "vertex";
+        let is_face = element.name == "face";
+
+        let slot = |name: &str| match name {
+            "x" => Some(0),
+            "y" => Some(1),
+            "z" => Some(2),
+            "nx" => Some(3),
+            "ny" => Some(4),
+            "nz" => Some(5),
+            "s" | "u" | "texture_u" => Some(6),
+            "t" | "v" | "texture_v" => Some(7),
+            "red" | "r" | "diffuse_red" => Some(8),
+            "green" | "g" | "diffuse_green" => Some(9),
+            "blue" | "b" | "diffuse_blue" => Some(10),
+            _ => None,
+        };
+        let mut present = [false; 11];
+        if is_vertex {
+            for (name, ty) in &element.properties {
+                match (slot(name), ty) {
+                    (Some(s), PropertyType::Scalar(_)) => present[s] = true,
+                    (None, PropertyType::Scalar(_)) => mesh.properties.push(Property {
+                        name: name.clone(),
+                        values: Vec::new(),
+                    }),
+                    _ => (),
+                }
+            }
+        }
+
+        for _ in 0..element.count {
+            let mut values = [0.0f32; 11];
+            let mut extra = 0;
+            for (name, ty) in &element.properties {
+                match ty {
+                    PropertyType::Scalar(s) => {
+                        let mut x = body.read(*s)? as f32;
+                        if !is_vertex {
+                            continue;
+                        }
+                        match slot(name) {
+                            Some(i) => {
+                                if i >= 8 {
+                                    match s {
+                                        Scalar::U8 => x /= 255.0,
+                                        Scalar::U16 => x /= 65535.0,
+                                        _ => (),
+                                    }
+                                }
+                                values[i] = x;
+                            }
+                            None => {
+                                mesh.properties[extra].values.push(x);
+                                extra += 1;
+                            }
+                        }
+                    }
+                    PropertyType::List(count, item) => {
+                        // grown as it is read, so a corrupt count runs out of
+                        // data instead of allocating it up front
+                        let index = |x: f64| {
+                            if x < 0.0 {
+                                Err(invalid(format!("negative PLY list value {}", x)))
+                            } else {
+                                Ok(x as usize)
+                            }
+                        };
+                        let n = index(body.read(*count)?)?;
+                        let mut list = Vec::new();
+                        for _ in 0..n {
+                            list.push(index(body.read(*item)?)?);
+                        }
+                        if is_face && (name == "vertex_indices" || name == "vertex_index") {
+                            mesh.faces.push(list);
+                        }
+                    }
+                }
+            }
+
+            if is_vertex {
+                mesh.positions.push([values[0], values[1], values[2]]);
+                normals.push([values[3], values[4], values[5]]);
+                uvs.push([values[6], values[7]]);
+                colors.push([values[8], values[9], values[10]]);
+            }
+        }
+
+        if is_vertex {
+            if present[3] {
+                mesh.normals = Some(std::mem::take(&mut normals));
+            }
+            if present[6] {
+                mesh.uvs = Some(std::mem::take(&mut uvs));
+            }
+            if present[8] {
+                mesh.colors = Some(std::mem::take(&mut colors));
+            }
+        }
+    }
+
+    if let Some(face) = mesh
+        .faces
+        .iter()
+        .find(|f| f.len() < 3 || f.iter().any(|&v| v >= mesh.positions.len()))
+    {
+        return Err(invalid(format!("bad PLY face {:?}", face)));
+    }
+
+    Ok(mesh)
+}
+
+fn write_value(out: &mut impl Write, format: PlyFormat, ty: Scalar, x: f64) -> io::Result<()> {
+    match (format, ty) {
+        (PlyFormat::Ascii, Scalar::F32) => write!(out, "{}", x as f32),
+        (PlyFormat::Ascii, _) => write!(out, "{}", x),
+        (PlyFormat::BinaryLittleEndian, Scalar::U8) => out.write_all(&[x as u8]),
+        (PlyFormat::BinaryLittleEndian, Scalar::I32) => out.write_all(&(x as i32).to_le_bytes()),
+        (PlyFormat::BinaryLittleEndian, Scalar::U32) => out.write_all(&(x as u32).to_le_bytes()),
+        (PlyFormat::BinaryLittleEndian, _) => out.write_all(&(x as f32).to_le_bytes()),
+        (PlyFormat::BinaryBigEndian, Scalar::U8) => out.write_all(&[x as u8]),
+        (PlyFormat::BinaryBigEndian, Scalar::I32) => out.write_all(&(x as i32).to_be_bytes()),
+        (PlyFormat::BinaryBigEndian, Scalar::U32) => out.write_all(&(x as u32).to_be_bytes()),
+        (PlyFormat::BinaryBigEndian, _) => out.write_all(&(x as f32).to_be_bytes()),
+    }
+}
+
+/// Writes the mesh with every vertex attribute it carries: normals, texture
+/// coordinates, colours (as `uchar`) and any extra float properties. Face
+/// sizes are `uchar` unless a face has more than 255 corners. Faces of
+/// a subdivided mesh also get their origin: `base_face`, `piece`, `level` and
+/// `path`, the children taken packed two bits per level with the first level
+/// highest.
+pub fn write_ply(mesh: &Mesh, path: &str, format: PlyFormat) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+
+    writeln!(out, "ply")?;
+    match format {
+        PlyFormat::Ascii => writeln!(out, "format ascii 1.0")?,
+        PlyFormat::BinaryLittleEndian => writeln!(out, "format binary_little_endian 1.0")?,
+        PlyFormat::BinaryBigEndian => writeln!(out, "format binary_big_endian 1.0")?,
+    }
+    writeln!(out, "element vertex {}", mesh.positions.len())?;
+    let mut columns: Vec<(&str, Scalar)> =
+        vec![("x", Scalar::F32), ("y", Scalar::F32), ("z", Scalar::F32)];
+    if mesh.normals.is_some() {
+        columns.extend([
+            ("nx", Scalar::F32),
+            ("ny", Scalar::F32),
+            ("nz", Scalar::F32),
+        ]);
+    }
+    if mesh.uvs.is_some() {
+        columns.extend([("s", Scalar::F32), ("t", Scalar::F32)]);
+    }
+    if mesh.colors.is_some() {
+        columns.extend([
+            ("red", Scalar::U8),
+            ("green", Scalar::U8),
+            ("blue", Scalar::U8),
+        ]);
+    }
+    for property in &mesh.properties {
+        columns.push((&property.name, Scalar::F32));
+    }
+    for (name, ty) in &columns {
+        let ty = if *ty == Scalar::U8 { "uchar" } else { "float" };
+        writeln!(out, "property {} {}", ty, name)?;
+    }
+    let count = if mesh.faces.iter().all(|f| f.len() <= u8::MAX as usize) {
+        Scalar::U8
+    } else {
+        Scalar::U32
+    };
+    writeln!(out, "element face {}", mesh.faces.len())?;
+    writeln!(
+        out,
+        "property list {} int vertex_indices",
+        if count == Scalar::U8 { "uchar" } else { "uint" }
+    )?;
+    if !mesh.origins.is_empty() {
+        writeln!(out, "property int base_face")?;
+        writeln!(out, "property int piece")?;
+        writeln!(out, "property uchar level")?;
+        writeln!(out, "property uint path")?;
+    }
+    writeln!(out, "end_header")?;
+
+    for i in 0..mesh.positions.len() {
+        let mut row: Vec<f32> = mesh.positions[i].to_vec();
+        if let Some(normals) = &mesh.normals {
+            row.extend(normals[i]);
+        }
+        if let Some(uvs) = &mesh.uvs {
+            row.extend(uvs[i]);
+        }
+        if let Some(colors) = &mesh.colors {
+            row.extend(
+                colors[i]
+                    .iter()
+                    .map(|c| (c * 255.0).round().clamp(0.0, 255.0)),
+            );
+        }
+        for property in &mesh.properties {
+            row.push(property.values[i]);
+        }
+
+        for (j, (x, (_, ty))) in row.iter().zip(&columns).enumerate() {
+            if format == PlyFormat::Ascii && j > 0 {
+                write!(out, " ")?;
+            }
+            write_value(&mut out, format, *ty, *x as f64)?;
+        }
+        if format == PlyFormat::Ascii {
+            writeln!(out)?;
+        }
+    }
+
+    for (f, face) in mesh.faces.iter().enumerate() {
+        write_value(&mut out, format, count, face.len() as f64)?;
+        for &v in face {
+            if format == PlyFormat::Ascii {
+                write!(out, " ")?;
+            }
+            write_value(&mut out, format, Scalar::I32, v as f64)?;
+        }
+        if let Some(origin) = mesh.origins.get(f) {
+            let path = origin
+                .path
+                .iter()
+                .fold(0u32, |p, &child| (p << 2) | child as u32);
+            for (ty, x) in [
+                (Scalar::I32, origin.face as f64),
+                (Scalar::I32, origin.piece as f64),
+                (Scalar::U8, origin.level() as f64),
+                (Scalar::U32, path as f64),
+            ] {
+                if format == PlyFormat::Ascii {
+                    write!(out, " ")?;
+                }
+                write_value(&mut out, format, ty, x)?;
+            }
+        }
+        if format == PlyFormat::Ascii {
+            writeln!(out)?;
+        }
+    }
+
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::temp_path;
+
+    fn square() -> Mesh {
+        Mesh {
+            positions: vec![
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.5],
+                [0.0, 1.0, 0.0],
+            ],
+            faces: vec![vec![0, 1, 2, 3]],
+            colors: Some(vec![
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 0.0, 1.0],
+                [1.0, 1.0, 1.0],
+            ]),
+            properties: vec![Property {
+                name: "confidence".to_string(),
+                values: vec![0.25, 0.5, 0.75, 1.0],
+            }],
+            ..Mesh::default()
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for format in [
+            PlyFormat::Ascii,
+            PlyFormat::BinaryLittleEndian,
+            PlyFormat::BinaryBigEndian,
+        ] {
+            let path = temp_path(&format!("{:?}.ply", format));
+            let mesh = square();
+            write_ply(&mesh, &path, format).unwrap();
+            let read = load_ply(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(read.positions, mesh.positions);
+            assert_eq!(read.faces, mesh.faces);
+            assert_eq!(read.colors, mesh.colors);
+            assert_eq!(read.properties.len(), 1);
+            assert_eq!(read.properties[0].name, "confidence");
+            assert_eq!(read.properties[0].values, mesh.properties[0].values);
+        }
+    }
+
+    #[test]
+    fn faces_with_more_than_255_corners() {
+        let n = 300;
+        let mesh = Mesh {
+            positions: (0..n)
+                .map(|i| {
+                    let a = i as f32 / n as f32 * std::f32::consts::TAU;
+                    [a.cos(), a.sin(), 0.0]
+                })
+                .collect(),
+            faces: vec![(0..n).collect()],
+            ..Mesh::default()
+        };
+        for format in [PlyFormat::Ascii, PlyFormat::BinaryLittleEndian] {
+            let path = temp_path(&format!("large-{:?}.ply", format));
+            write_ply(&mesh, &path, format).unwrap();
+            let read = load_ply(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(read.faces, mesh.faces);
+        }
+    }
+
+    #[test]
+    fn ushort_colors_are_normalised() {
+        let path = temp_path("ushort.ply");
+        std::fs::write(
+            &path,
+            "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+             property float z\nproperty ushort red\nproperty ushort green\n\
+             property ushort blue\nelement face 1\nproperty list uchar int vertex_indices\n\
+             end_header\n0 0 0 65535 0 0\n1 0 0 0 65535 0\n0 1 0 0 0 0\n3 0 1 2\n",
+        )
+        .unwrap();
+        let read = load_ply(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            read.colors,
+            Some(vec![[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 0.0]])
+        );
+    }
+
+    #[test]
+    fn rejects_negative_and_oversized_lists() {
+        let path = temp_path("lists.ply");
+        for face in ["3 0 1 -1", "-3 0 1 2", "4000000000 0 1 2"] {
+            std::fs::write(
+                &path,
+                format!(
+                    "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\n\
+                     property float y\nproperty float z\nelement face 1\n\
+                     property list int int vertex_indices\nend_header\n\
+                     0 0 0\n1 0 0\n0 1 0\n{}\n",
+                    face
+                ),
+            )
+            .unwrap();
+            let error = load_ply(&path).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", face);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
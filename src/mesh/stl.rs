@@ -0,0 +1,187 @@
+use crate::mesh::{face_normal, Mesh};
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufWriter};
+use vecmath::*;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Loads an ASCII or binary STL file. STL stores every triangle with its own
+/// copy of its corners, so corners closer than `tolerance` are welded into
+/// shared vertices to give the subdividers a connected mesh. Bytes after the
+/// last binary triangle are ignored unless the header reads like ASCII.
+pub fn load_stl(path: &str, tolerance: f32) -> io::Result<Mesh> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+
+    let mut mesh = Mesh::default();
+
+    let binary_count = bytes
+        .get(80..84)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+    let ascii = bytes.starts_with(b"solid");
+    match binary_count {
+        Some(n) if bytes.len() == 84 + 50 * n || (!ascii && bytes.len() > 84 + 50 * n) => {
+            for i in 0..n {
+                let record = &bytes[84 + 50 * i..84 + 50 * (i + 1)];
+                let mut face = Vec::new();
+                for corner in 0..3 {
+                    let mut p = [0.0; 3];
+                    for (k, x) in p.iter_mut().enumerate() {
+                        let at = 12 + 12 * corner + 4 * k;
+                        *x = f32::from_le_bytes([
+                            record[at],
+                            record[at + 1],
+                            record[at + 2],
+                            record[at + 3],
+                        ]);
+                    }
+                    face.push(mesh.positions.len());
+                    mesh.positions.push(p);
+                }
+                mesh.faces.push(face);
+            }
+        }
+        _ => {
+            let text = std::str::from_utf8(&bytes).map_err(|_| invalid("not an STL file"))?;
+            if !text.trim_start().starts_with("solid") {
+                return Err(invalid("not an STL file"));
+            }
+            let mut face = Vec::new();
+            for line in text.lines() {
+                let words: Vec<&str> = line.split_whitespace().collect();
+                match words.as_slice() {
+                    ["vertex", x, y, z] => {
+                        let mut p = [0.0; 3];
+                        for (v, w) in p.iter_mut().zip([x, y, z]) {
+                            *v = w.parse().map_err(|_| invalid("bad STL vertex"))?;
+                        }
+                        face.push(mesh.positions.len());
+                        mesh.positions.push(p);
+                    }
+                    ["endfacet"] => {
+                        if face.len() != 3 {
+                            return Err(invalid("STL facet without three vertices"));
+                        }
+                        mesh.faces.push(std::mem::take(&mut face));
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    mesh.weld(tolerance);
+    Ok(mesh)
+}
+
+/// Writes the mesh as binary STL. Faces with more than three corners are
+/// fanned into triangles, and those with fewer are left out.
+pub fn write_stl(mesh: &Mesh, path: &str) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+
+    let mut header = [0u8; 80];
+    let text = b"binary STL written by subdivision";
+    header[..text.len()].copy_from_slice(text);
+    out.write_all(&header)?;
+
+    let faces = || mesh.faces.iter().filter(|f| f.len() >= 3);
+    let count: usize = faces().map(|f| f.len() - 2).sum();
+    out.write_all(&(count as u32).to_le_bytes())?;
+
+    for face in faces() {
+        for i in 1..face.len() - 1 {
+            let triangle = [face[0], face[i], face[i + 1]];
+            let mut n = face_normal(&mesh.positions, &triangle);
+            if vec3_len(n) > 0.0 {
+                n = vec3_normalized(n);
+            }
+            for x in n {
+                out.write_all(&x.to_le_bytes())?;
+            }
+            for v in triangle {
+                for x in mesh.positions[v] {
+                    out.write_all(&x.to_le_bytes())?;
+                }
+            }
+            out.write_all(&[0, 0])?;
+        }
+    }
+
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::{self, temp_path};
+    use crate::stats::Stats;
+    use crate::validate;
+
+    #[test]
+    fn ascii_and_binary_round_trip() {
+        let cube = mesh::load("assets/quad_cube.obj").unwrap();
+        let binary = temp_path("cube.stl");
+        write_stl(&cube, &binary).unwrap();
+
+        // the same triangles as ASCII
+        let read = load_stl(&binary, 1e-4).unwrap();
+        let mut text = String::from("solid cube\n");
+        for face in &read.faces {
+            text += "facet normal 0 0 0\nouter loop\n";
+            for &v in face {
+                let p = read.positions[v];
+                text += &format!("vertex {} {} {}\n", p[0], p[1], p[2]);
+            }
+            text += "endloop\nendfacet\n";
+        }
+        text += "endsolid cube\n";
+        let ascii = temp_path("cube-ascii.stl");
+        std::fs::write(&ascii, text).unwrap();
+        let read_ascii = load_stl(&ascii, 1e-4).unwrap();
+
+        // trailing bytes after the triangles are ignored
+        let mut bytes = std::fs::read(&binary).unwrap();
+        bytes.extend_from_slice(b"trailing");
+        std::fs::write(&binary, bytes).unwrap();
+        let read_padded = load_stl(&binary, 1e-4).unwrap();
+
+        std::fs::remove_file(&binary).unwrap();
+        std::fs::remove_file(&ascii).unwrap();
+        for mesh in [&read, &read_ascii, &read_padded] {
+            assert_eq!(mesh.faces.len(), 12);
+            assert_eq!(mesh.positions, read.positions);
+            assert_eq!(mesh.faces, read.faces);
+        }
+    }
+
+    #[test]
+    fn welds_into_a_closed_cube() {
+        let cube = mesh::load("assets/quad_cube.obj").unwrap();
+        let path = temp_path("closed.stl");
+        write_stl(&cube, &path).unwrap();
+        let read = load_stl(&path, 1e-4).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.positions.len(), 8);
+        assert!(validate::validate(&read).is_empty());
+        assert_eq!(Stats::compute(&read).boundary_loops, 0);
+    }
+
+    #[test]
+    fn skips_faces_with_fewer_than_three_corners() {
+        let mesh = Mesh {
+            positions: vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+            faces: vec![vec![0, 1, 2], vec![0, 1], vec![]],
+            ..Mesh::default()
+        };
+        let path = temp_path("short.stl");
+        write_stl(&mesh, &path).unwrap();
+        let read = load_stl(&path, 1e-4).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.faces, vec![vec![0, 1, 2]]);
+    }
+}
@@ -0,0 +1,191 @@
+use crate::mesh::Mesh;
+use crate::scheme::Scheme;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufWriter};
+
+/// USD treats sharpness 10 and above as infinitely sharp.
+const SHARP: f32 = 10.0;
+
+fn list<T: ToString>(values: impl IntoIterator<Item = T>) -> String {
+    let values: Vec<String> = values.into_iter().map(|v| v.to_string()).collect();
+    format!("[{}]", values.join(", "))
+}
+
+/// Writes the mesh as the control cage of a `UsdGeomMesh` so a renderer can
+/// subdivide it natively. With `creases` the edges and vertices this tool
+/// would keep sharp are written as infinitely sharp creases and corners.
+pub fn write_usda(mesh: &Mesh, path: &str, scheme: Scheme, creases: Option<f32>) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+
+    // Loop needs triangles, fan them the same way the triangle loader does
+    let faces: Vec<Vec<usize>> = match scheme {
+        Scheme::Loop => mesh
+            .faces
+            .iter()
+            .flat_map(|f| (1..f.len().saturating_sub(1)).map(move |i| vec![f[0], f[i], f[i + 1]]))
+            .collect(),
+        Scheme::CatmullClark => mesh.faces.clone(),
+    };
+
+    writeln!(out, "#usda 1.0")?;
+    writeln!(out, "(")?;
+    writeln!(out, "    defaultPrim = \"Mesh\"")?;
+    writeln!(out, "    upAxis = \"Y\"")?;
+    writeln!(out, ")")?;
+    writeln!(out)?;
+    writeln!(out, "def Mesh \"Mesh\"")?;
+    writeln!(out, "{{")?;
+    writeln!(
+        out,
+        "    int[] faceVertexCounts = {}",
+        list(faces.iter().map(|f| f.len()))
+    )?;
+    writeln!(
+        out,
+        "    int[] faceVertexIndices = {}",
+        list(faces.iter().flatten())
+    )?;
+    let points: Vec<String> = mesh
+        .positions
+        .iter()
+        .map(|p| format!("({}, {}, {})", p[0], p[1], p[2]))
+        .collect();
+    writeln!(out, "    point3f[] points = [{}]", points.join(", "))?;
+    writeln!(out, "    uniform token orientation = \"rightHanded\"")?;
+    let name = match scheme {
+        Scheme::Loop => "loop",
+        Scheme::CatmullClark => "catmullClark",
+    };
+    writeln!(out, "    uniform token subdivisionScheme = \"{}\"", name)?;
+
+    if let Some(cosine) = creases {
+        // found on the faces written, which are the ones the renderer sees
+        let cage = Mesh {
+            positions: mesh.positions.clone(),
+            faces,
+            ..Mesh::default()
+        };
+        let creases = cage.creases(cosine);
+        if !creases.edges.is_empty() {
+            writeln!(
+                out,
+                "    int[] creaseIndices = {}",
+                list(creases.edges.iter().flatten())
+            )?;
+            writeln!(
+                out,
+                "    int[] creaseLengths = {}",
+                list(creases.edges.iter().map(|_| 2))
+            )?;
+            writeln!(
+                out,
+                "    float[] creaseSharpnesses = {}",
+                list(creases.edges.iter().map(|_| SHARP))
+            )?;
+        }
+        if !creases.corners.is_empty() {
+            writeln!(out, "    int[] cornerIndices = {}", list(&creases.corners))?;
+            writeln!(
+                out,
+                "    float[] cornerSharpnesses = {}",
+                list(creases.corners.iter().map(|_| SHARP))
+            )?;
+        }
+    }
+
+    if let Some(uvs) = &mesh.uvs {
+        let uvs: Vec<String> = uvs
+            .iter()
+            .map(|t| format!("({}, {})", t[0], t[1]))
+            .collect();
+        writeln!(out, "    texCoord2f[] primvars:st = [{}] (", uvs.join(", "))?;
+        writeln!(out, "        interpolation = \"vertex\"")?;
+        writeln!(out, "    )")?;
+    }
+    if let Some(colors) = &mesh.colors {
+        let colors: Vec<String> = colors
+            .iter()
+            .map(|c| format!("({}, {}, {})", c[0], c[1], c[2]))
+            .collect();
+        writeln!(
+            out,
+            "    color3f[] primvars:displayColor = [{}] (",
+            colors.join(", ")
+        )?;
+        writeln!(out, "        interpolation = \"vertex\"")?;
+        writeln!(out, "    )")?;
+    }
+    writeln!(out, "}}")?;
+
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::{self, temp_path};
+
+    /// The values of the array attribute `name`.
+    fn array(text: &str, name: &str) -> Vec<f32> {
+        let line = text
+            .lines()
+            .find(|l| l.contains(&format!("] {} = ", name)))
+            .unwrap_or_else(|| panic!("no {}", name));
+        let values = line.split_once(" = [").unwrap().1.trim_end_matches(']');
+        values.split(", ").map(|v| v.parse().unwrap()).collect()
+    }
+
+    fn write(mesh: &Mesh, scheme: Scheme) -> String {
+        let path = temp_path(&format!("{}.usda", scheme));
+        write_usda(mesh, &path, scheme, Some(0.6)).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        text
+    }
+
+    #[test]
+    fn cube_with_creases() {
+        let cube = mesh::load("assets/cube.obj").unwrap();
+        let text = write(&cube, Scheme::Loop);
+        assert_eq!(array(&text, "faceVertexCounts"), [3.0; 12]);
+        assert!(text.contains(r#"uniform token subdivisionScheme = "loop""#));
+
+        // the twelve cube edges, not the diagonals across the sides
+        let indices = array(&text, "creaseIndices");
+        assert_eq!(indices.len(), 24);
+        assert_eq!(array(&text, "creaseLengths"), [2.0; 12]);
+        assert_eq!(array(&text, "creaseSharpnesses"), [SHARP; 12]);
+        for edge in indices.chunks(2) {
+            let [a, b] = [edge[0], edge[1]].map(|v| cube.positions[v as usize]);
+            let same = (0..3).filter(|&k| a[k] == b[k]).count();
+            assert_eq!(same, 2, "{:?} is a diagonal", edge);
+        }
+        assert_eq!(array(&text, "cornerIndices").len(), 8);
+    }
+
+    #[test]
+    fn fanned_quads_crease_their_own_edges() {
+        // a quad folded along its diagonal from 0 to 2
+        let folded = Mesh {
+            positions: vec![
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 0.0, 1.0],
+            ],
+            faces: vec![vec![0, 1, 2, 3]],
+            ..Mesh::default()
+        };
+        let text = write(&folded, Scheme::Loop);
+        assert_eq!(array(&text, "faceVertexCounts"), [3.0; 2]);
+        assert_eq!(array(&text, "creaseIndices"), [0.0, 2.0]);
+
+        let cube = mesh::load("assets/quad_cube.obj").unwrap();
+        let text = write(&cube, Scheme::CatmullClark);
+        assert_eq!(array(&text, "faceVertexCounts"), [4.0; 6]);
+        assert!(text.contains(r#"uniform token subdivisionScheme = "catmullClark""#));
+        assert_eq!(array(&text, "creaseLengths"), [2.0; 12]);
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Threads used by `flat_map`, 0 for one per core.
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+
+/// Fewest items worth handing to a thread of their own.
+const MIN_CHUNK: usize = 1024;
+
+/// Sets how many threads `flat_map` uses, 0 for one per core.
+pub fn set_threads(threads: usize) {
+    THREADS.store(threads, Ordering::Relaxed);
+}
+
+fn threads() -> usize {
+    match THREADS.load(Ordering::Relaxed) {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+}
+
+/// Calls `f` on every item, letting it append to the output, and returns
+/// the outputs in item order. The items are split into one chunk per thread
+/// and the chunks' outputs joined in order, so the result is identical to a
+/// serial loop's.
+pub fn flat_map<T, U, F>(items: &[T], f: F) -> Vec<U>
+where
+    T: Sync,
+    U: Send,
+    F: Fn(&T, &mut Vec<U>) + Sync,
+{
+    let run = |chunk: &[T]| {
+        let mut r = Vec::new();
+        for item in chunk {
+            f(item, &mut r);
+        }
+        r
+    };
+
+    let threads = threads().min(items.len() / MIN_CHUNK);
+    if threads <= 1 {
+        return run(items);
+    }
+    let chunk = items.len().div_ceil(threads);
+    thread::scope(|s| {
+        let handles: Vec<_> = items
+            .chunks(chunk)
+            .map(|chunk| s.spawn(|| run(chunk)))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    })
+}
+
+/// `f` of every item, in item order, split across threads like `flat_map`.
+pub fn map<T, U, F>(items: &[T], f: F) -> Vec<U>
+where
+    T: Sync,
+    U: Send,
+    F: Fn(&T) -> U + Sync,
+{
+    flat_map(items, |item, r| r.push(f(item)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh;
+    use crate::scheme::{self, Scheme};
+
+    #[test]
+    fn threads_do_not_change_the_result() {
+        for (path, scheme, levels) in [
+            ("assets/cube.obj", Scheme::Loop, 4),
+            ("assets/quad_cube.obj", Scheme::CatmullClark, 5),
+        ] {
+            let base = mesh::load(path).unwrap();
+            let run = |threads| {
+                set_threads(threads);
+                scheme::subdivide(scheme, &base, 1, levels, Some(mesh::CREASE_COSINE))
+            };
+            let serial = run(1);
+            let threaded = run(4);
+            set_threads(0);
+            // the last step splits its faces into several chunks
+            assert!(serial.faces.len() / 4 >= 3 * MIN_CHUNK);
+            assert_eq!(serial.positions, threaded.positions);
+            assert_eq!(serial.faces, threaded.faces);
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use crate::mesh::Mesh;
+use crate::scheme::Scheme;
+
+use image::{GenericImage, RgbaImage};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Where the per-face textures come from.
+#[derive(Debug, Clone)]
+pub enum TextureSource {
+    /// One image per Ptex face, named after its index, such as `12.png`.
+    Directory(String),
+    /// One image and a text file with a rectangle for each Ptex face.
+    Atlas { image: String, rects: String },
+}
+
+/// A pixel rectangle of the atlas.
+#[derive(Debug, Clone, Copy)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Textures for each face of a control cage in the manner of Ptex, so that
+/// the subdivided mesh can be textured without a UV unwrap. Quads are one
+/// Ptex face each, and every other polygon is one Ptex face per corner, the
+/// quads Catmull-Clark splits it into. All of them share one atlas image.
+#[derive(Clone)]
+pub struct FaceTextures {
+    pub atlas: RgbaImage,
+    pub rects: Vec<Rect>,
+}
+
+impl FaceTextures {
+    /// Loads the textures for the Ptex faces of `base`. Every face needs one.
+    pub fn load(source: &TextureSource, base: &Mesh) -> Result<FaceTextures, Box<dyn Error>> {
+        let count = ptex_faces(base).1;
+        let textures = match source {
+            TextureSource::Directory(dir) => from_directory(dir, count)?,
+            TextureSource::Atlas { image, rects } => FaceTextures {
+                atlas: image::open(image)
+                    .map_err(|e| format!("{}: {}", image, e))?
+                    .to_rgba8(),
+                rects: read_rects(rects)?,
+            },
+        };
+
+        textures.check(base)?;
+        let (width, height) = textures.atlas.dimensions();
+        let inside = |start: u32, size: u32, limit: u32| {
+            size > 0 && start.checked_add(size).is_some_and(|end| end <= limit)
+        };
+        if let Some(i) = textures
+            .rects
+            .iter()
+            .position(|r| !inside(r.x, r.width, width) || !inside(r.y, r.height, height))
+        {
+            return Err(format!("rectangle {} is empty or outside the atlas", i).into());
+        }
+        Ok(textures)
+    }
+
+    /// Fails unless there is a texture for every Ptex face of `base`, as
+    /// `apply` needs.
+    pub fn check(&self, base: &Mesh) -> Result<(), String> {
+        let count = ptex_faces(base).1;
+        if self.rects.len() < count {
+            return Err(format!(
+                "the mesh has {} Ptex faces but only {} textures",
+                count,
+                self.rects.len()
+            ));
+        }
+        Ok(())
+    }
+
+    /// The atlas coordinates of `(u, v)` in Ptex face `face`, with v running
+    /// up the image. Samples stay half a texel inside the face's rectangle so
+    /// that neighbours in the atlas do not bleed in.
+    fn resolve(&self, face: usize, u: f32, v: f32) -> [f32; 2] {
+        let (width, height) = self.atlas.dimensions();
+        let r = *self
+            .rects
+            .get(face)
+            .expect("a texture for every Ptex face, as checked on load");
+        let x = r.x as f32 + 0.5 + u * (r.width as f32 - 1.0);
+        let y = r.y as f32 + 0.5 + (1.0 - v) * (r.height as f32 - 1.0);
+        [x / width as f32, 1.0 - y / height as f32]
+    }
+
+    /// `mesh`, a Catmull-Clark subdivision, with texture coordinates into
+    /// the atlas. Vertices on the border between two Ptex faces are split,
+    /// one copy per face, so each can carry its own coordinates.
+    pub fn apply(&self, base: &Mesh, mesh: &Mesh) -> Mesh {
+        let first = ptex_faces(base).0;
+        let mut copies: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut kept = Vec::new();
+        let mut uvs = Vec::new();
+        let mut faces = Vec::new();
+
+        for (face, origin) in mesh.faces.iter().zip(&mesh.origins) {
+            let ptex = first[origin.face] + origin.piece;
+            let corners = square(&origin.path);
+            faces.push(
+                face.iter()
+                    .zip(corners)
+                    .map(|(&v, [u, w])| {
+                        *copies.entry((v, ptex)).or_insert_with(|| {
+                            kept.push(v);
+                            uvs.push(self.resolve(ptex, u, w));
+                            kept.len() - 1
+                        })
+                    })
+                    .collect(),
+            );
+        }
+
+        let pick = |values: &[f32]| -> Vec<f32> { kept.iter().map(|&v| values[v]).collect() };
+        Mesh {
+            positions: kept.iter().map(|&v| mesh.positions[v]).collect(),
+            faces,
+            normals: mesh
+                .normals
+                .as_ref()
+                .map(|n| kept.iter().map(|&v| n[v]).collect()),
+            uvs: Some(uvs),
+            colors: mesh
+                .colors
+                .as_ref()
+                .map(|c| kept.iter().map(|&v| c[v]).collect()),
+            properties: mesh
+                .properties
+                .iter()
+                .map(|p| crate::mesh::Property {
+                    name: p.name.clone(),
+                    values: pick(&p.values),
+                })
+                .collect(),
+            groups: mesh.groups.clone(),
+            face_groups: mesh.face_groups.clone(),
+            origins: mesh.origins.clone(),
+        }
+    }
+
+    /// Writes the atlas next to `output` as `<name>_atlas.png`, for the
+    /// coordinates `apply` gave it, and returns its path.
+    pub fn save_atlas(&self, output: &str) -> Result<String, Box<dyn Error>> {
+        let path = Path::new(output);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let atlas = path
+            .with_file_name(format!("{}_atlas.png", stem))
+            .to_string_lossy()
+            .into_owned();
+        self.atlas.save(&atlas)?;
+        Ok(atlas)
+    }
+}
+
+/// Loads the textures from `source`, if there is one, for subdividing `base`
+/// with `scheme`. Only Catmull-Clark output is made of quads that map onto
+/// Ptex faces.
+pub fn load(
+    source: Option<TextureSource>,
+    base: &Mesh,
+    scheme: Scheme,
+) -> Result<Option<FaceTextures>, Box<dyn Error>> {
+    let Some(source) = source else {
+        return Ok(None);
+    };
+    if scheme != Scheme::CatmullClark {
+        return Err("per-face textures need the Catmull-Clark scheme".into());
+    }
+    FaceTextures::load(&source, base).map(Some)
+}
+
+/// The first Ptex face of every base face, and how many there are in all.
+pub fn ptex_faces(base: &Mesh) -> (Vec<usize>, usize) {
+    let mut first = Vec::with_capacity(base.faces.len());
+    let mut count = 0;
+    for face in &base.faces {
+        first.push(count);
+        count += if face.len() == 4 { 1 } else { face.len() };
+    }
+    (first, count)
+}
+
+/// The corners, in the dividers' order, of the square a Catmull-Clark face
+/// covers in its Ptex face. Every child keeps its parent's axes, taking the
+/// corner quarter the child index names.
+fn square(path: &[u8]) -> [[f32; 2]; 4] {
+    const OFFSETS: [[f32; 2]; 4] = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+    let mut origin = [0.0, 0.0];
+    let mut size = 1.0;
+    for &child in path {
+        size *= 0.5;
+        origin[0] += OFFSETS[child as usize][0] * size;
+        origin[1] += OFFSETS[child as usize][1] * size;
+    }
+    OFFSETS.map(|o| [origin[0] + o[0] * size, origin[1] + o[1] * size])
+}
+
+/// Packs `<dir>/<face>.<ext>` for every Ptex face into a grid of equal
+/// cells.
+fn from_directory(dir: &str, count: usize) -> Result<FaceTextures, Box<dyn Error>> {
+    let mut files = HashMap::new();
+    for entry in fs::read_dir(dir).map_err(|e| format!("{}: {}", dir, e))? {
+        let path = entry?.path();
+        if let Some(face) = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse::<usize>().ok())
+        {
+            files.insert(face, path);
+        }
+    }
+
+    let mut images = Vec::with_capacity(count);
+    for face in 0..count {
+        let path = files
+            .get(&face)
+            .ok_or_else(|| format!("{}: no texture for Ptex face {}", dir, face))?;
+        let image = image::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        images.push(image.to_rgba8());
+    }
+
+    let cell_width = images.iter().map(|i| i.width()).max().unwrap_or(1);
+    let cell_height = images.iter().map(|i| i.height()).max().unwrap_or(1);
+    let columns = (count as f64).sqrt().ceil().max(1.0) as u32;
+    let rows = (count as u32).div_ceil(columns).max(1);
+    let mut atlas = RgbaImage::new(columns * cell_width, rows * cell_height);
+    let mut rects = Vec::with_capacity(count);
+    for (i, image) in images.iter().enumerate() {
+        let x = (i as u32 % columns) * cell_width;
+        let y = (i as u32 / columns) * cell_height;
+        atlas.copy_from(image, x, y)?;
+        rects.push(Rect {
+            x,
+            y,
+            width: image.width(),
+            height: image.height(),
+        });
+    }
+    Ok(FaceTextures { atlas, rects })
+}
+
+/// Reads one `x y width height` line in pixels per Ptex face, in order.
+/// Blank lines and lines starting with `#` are skipped.
+fn read_rects(path: &str) -> Result<Vec<Rect>, Box<dyn Error>> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut rects = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let numbers: Vec<u32> = line
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("{}:{}: {}", path, i + 1, e))?;
+        match numbers[..] {
+            [x, y, width, height] => rects.push(Rect {
+                x,
+                y,
+                width,
+                height,
+            }),
+            _ => return Err(format!("{}:{}: expected x y width height", path, i + 1).into()),
+        }
+    }
+    Ok(rects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::{self, temp_path};
+
+    #[test]
+    fn checks_the_rectangles() {
+        // the cube has six Ptex faces
+        let base = mesh::load("assets/quad_cube.obj").unwrap();
+        let image = temp_path("atlas.png");
+        let rects = temp_path("atlas.txt");
+        RgbaImage::new(4, 4).save(&image).unwrap();
+        let source = TextureSource::Atlas {
+            image: image.clone(),
+            rects: rects.clone(),
+        };
+        let load = |last: &str| {
+            fs::write(&rects, "0 0 2 2\n".repeat(5) + last).unwrap();
+            FaceTextures::load(&source, &base)
+        };
+
+        assert!(load("2 2 2 2\n").is_ok());
+        assert!(load("").is_err());
+        assert!(load("3 0 2 2\n").is_err());
+        assert!(load("4294967295 0 2 2\n").is_err());
+
+        fs::remove_file(&image).unwrap();
+        fs::remove_file(&rects).unwrap();
+    }
+}
@@ -1,4 +1,7 @@
+use crate::mesh::Origin;
+use crate::parallel;
 use crate::quad_subdivide::*;
+use crate::topology::Topology;
 use vecmath::*;
 
 fn center(q: &Quad) -> Vertex {
@@ -27,6 +30,7 @@ fn average_n(a: &Vertex, b: &Vertex, x: f32, y: f32) -> Vertex {
             vec3_scale(a.position, x / (x + y)),
             vec3_scale(b.position, y / (x + y)),
         ),
+        base: None,
     }
 }
 
@@ -68,7 +72,30 @@ pub fn linear_subdivide_once(q: Vec<Quad>) -> Vec<Quad> {
     quads
 }
 
-pub fn catmull_subdivide(quads: Vec<Quad>, levels: u32, creases: bool) -> Vec<Quad> {
+/// Corner weights of the four children made by `linear_subdivide_once` and
+/// `catmull_subdivide_once`, in the order they are pushed.
+const CHILDREN: [[[f32; 4]; 4]; 4] = [
+    [[1.0, 0.0, 0.0, 0.0], [0.5, 0.5, 0.0, 0.0], [0.25, 0.25, 0.25, 0.25], [0.5, 0.0, 0.0, 0.5]],
+    [[0.5, 0.5, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.5, 0.5, 0.0], [0.25, 0.25, 0.25, 0.25]],
+    [[0.25, 0.25, 0.25, 0.25], [0.0, 0.5, 0.5, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.5, 0.5]],
+    [[0.5, 0.0, 0.0, 0.5], [0.25, 0.25, 0.25, 0.25], [0.0, 0.0, 0.5, 0.5], [0.0, 0.0, 0.0, 1.0]],
+];
+
+/// Follows the quads' origins through `levels` subdivision steps.
+pub fn subdivide_origins(origins: Vec<Origin>, levels: u32) -> Vec<Origin> {
+    let mut origins = origins;
+    for _ in 0..levels {
+        origins = origins
+            .iter()
+            .flat_map(|o| (0..).zip(&CHILDREN).map(move |(i, c)| o.child(i, c)))
+            .collect();
+    }
+    origins
+}
+
+/// With `creases` set, neighbouring quads whose normals have a dot product
+/// below it are kept sharp.
+pub fn catmull_subdivide(quads: Vec<Quad>, levels: u32, creases: Option<f32>) -> Vec<Quad> {
     let mut quads = quads;
     for _ in 0..levels {
         quads = catmull_subdivide_once(quads, creases)
@@ -76,123 +103,109 @@ pub fn catmull_subdivide(quads: Vec<Quad>, levels: u32, creases: bool) -> Vec<Qu
     quads
 }
 
-pub fn get_edge(
-    a: &Vertex,
-    b: &Vertex,
-    me: &Quad,
-    map: &HashMap<&Vertex, HashSet<&Quad>>,
-    creases: bool
+/// The Catmull-Clark edge point of edge `e`: the average of its midpoint and
+/// the face points on either side, or just the midpoint on a boundary or a
+/// sharp crease.
+fn edge_point(
+    topology: &Topology,
+    e: usize,
+    positions: &[[f32; 3]],
+    centers: &[Vertex],
+    normals: &[[f32; 3]],
+    creases: Option<f32>,
 ) -> Vertex {
-    if let Some(neighbor_quad) = get_adjacent(a, b, me, map) {
-        let neighbor_center = center(neighbor_quad);
-        let my_center = center(me);
-        let edge_center = average(a, b);
-
-        let mut s = 0.0;
-        if creases {
-            let n1 = normal(me);
-            let n2 = normal(neighbor_quad);
-            s = vec3_dot(n1,n2);
-        }
-
-        if creases && s < 0.6 {
-            average(a, b)
-        }
-        else {
-            average(&average(&my_center, &neighbor_center), &edge_center)
+    let [a, b] = topology.edges[e];
+    let a = Vertex { position: positions[a], base: None };
+    let b = Vertex { position: positions[b], base: None };
+    let f = topology.edge_faces[e][0];
+    match topology.across(e, f) {
+        Some(g) if !creases.is_some_and(|c| vec3_dot(normals[f], normals[g]) < c) => {
+            average(&average(&centers[f], &centers[g]), &average(&a, &b))
         }
-    }
-    else {
-        average(a, b)
+        _ => average(&a, &b),
     }
 }
 
-pub fn get_edge_midpoints(a: &Vertex, q: &Quad) -> (Vertex, Vertex) {
-    for i in 0..4 {
-        if *a == q.vertices[i] {
-            let i1 = if i == 0 { 3 } else { i - 1 };
-            let i2 = if i + 1 > 3 { 0 } else { i + 1 };
-            return (average(a, &q.vertices[i1]), average(a, &q.vertices[i2]));
+/// The Catmull-Clark vertex point of vertex `v`, which stays where it is
+/// when two of its faces meet at a sharp crease.
+fn vertex_point(
+    topology: &Topology,
+    v: usize,
+    vertices: &[Vertex],
+    faces: &[Vec<usize>],
+    centers: &[Vertex],
+    normals: &[[f32; 3]],
+    creases: Option<f32>,
+) -> Vertex {
+    let around = &topology.vertex_faces[v];
+    let n = around.len();
+    let a = vertices[v].position;
+
+    if let Some(c) = creases {
+        let mut s = 1.0f32;
+        for &f in around {
+            for &g in around {
+                if f != g {
+                    s = s.min(vec3_dot(normals[f], normals[g]));
+                }
+            }
+        }
+        if s < c {
+            return Vertex { position: a, base: vertices[v].base };
         }
     }
 
-    panic!("vertex was not part of quad");
-}
-
-pub fn compute_original(a: &Vertex, map: &HashMap<&Vertex, HashSet<&Quad>>, creases: bool) -> Vertex {
-    let neighbors = map.get(a).unwrap();
-    let n = neighbors.len();
     let mut pos = [0.0, 0.0, 0.0];
-    let mut norms = Vec::new();
-    for quad in neighbors {
-        pos = vec3_add(pos, center(quad).position);
-        if creases {
-            norms.push(normal(quad));
-        }
+    for &f in around {
+        pos = vec3_add(pos, centers[f].position);
     }
+    let f = vec3_scale(pos, 1.0 / (n as f32));
 
-    let mut s = 1.0;
-    if creases {
-        for i in 0..norms.len() {
-            for j in 0..norms.len() {
-                if i != j {
-                    let ss = vec3_dot(norms[i],norms[j]);
-                    if ss < s {
-                        s = ss;
-                    }
-                }
-            }
+    // the midpoints of the two edges of each face that meet at the vertex
+    let mut pos = [0.0, 0.0, 0.0];
+    for &q in around {
+        let face = &faces[q];
+        let i = face.iter().position(|&x| x == v).unwrap();
+        for j in [(i + 3) % 4, (i + 1) % 4] {
+            pos = vec3_add(pos, vec3_scale(vec3_add(a, vertices[face[j]].position), 0.5));
         }
     }
+    let r = vec3_scale(pos, 1.0 / (n as f32 * 2.0));
 
-    if creases && s < 0.6 {
-        a.clone()
-    }
-    else {
-        let f = vec3_scale(pos, 1.0 / (n as f32));
-
-        let mut pos = [0.0, 0.0, 0.0];
-        for quad in neighbors {
-            let (p1, p2) = get_edge_midpoints(&a, &quad);
-            pos = vec3_add(pos, p1.position);
-            pos = vec3_add(pos, p2.position);
-        }
-
-        let r = vec3_scale(pos, 1.0 / (n as f32 * 2.0));
-
-        let p = vec3_scale(
-            vec3_add(
-                vec3_add(f, vec3_scale(r, 2.0)),
-                vec3_scale(a.position, n as f32 - 3.0),
-            ),
-            1.0 / (n as f32),
-        );
-
-        Vertex { position: p }
-    }
+    let p = vec3_scale(
+        vec3_add(vec3_add(f, vec3_scale(r, 2.0)), vec3_scale(a, n as f32 - 3.0)),
+        1.0 / (n as f32),
+    );
+    Vertex { position: p, base: vertices[v].base }
 }
 
-pub fn catmull_subdivide_once(quads: Vec<Quad>, creases: bool) -> Vec<Quad> {
-    let map = get_vertex_map(&quads);
-    let mut r: Vec<Quad> = Vec::new();
-    for quad in &quads[..] {
-        let a = quad.vertices[0];
-        let b = quad.vertices[1];
-        let c = quad.vertices[2];
-        let d = quad.vertices[3];
-
-        // face point
-        let center_p = center(quad);
-
-        let e1 = get_edge(&a, &b, &quad, &map, creases);
-        let e2 = get_edge(&b, &c, &quad, &map, creases);
-        let e3 = get_edge(&c, &d, &quad, &map, creases);
-        let e4 = get_edge(&a, &d, &quad, &map, creases);
-
-        let na = compute_original(&a, &map, creases);
-        let nb = compute_original(&b, &map, creases);
-        let nc = compute_original(&c, &map, creases);
-        let nd = compute_original(&d, &map, creases);
+/// One Catmull-Clark step. The quads are welded into indexed form once, so
+/// that every face, edge and vertex point is worked out a single time and
+/// shared by the children that use it.
+pub fn catmull_subdivide_once(quads: Vec<Quad>, creases: Option<f32>) -> Vec<Quad> {
+    let (welded, faces) = weld(&quads);
+    let positions: Vec<[f32; 3]> = welded.iter().map(|v| v.position).collect();
+    let topology = Topology::new(positions.len(), &faces);
+
+    let centers = parallel::map(&quads, center);
+    let normals = match creases {
+        Some(_) => parallel::map(&quads, normal),
+        None => Vec::new(),
+    };
+    let edges: Vec<usize> = (0..topology.edges.len()).collect();
+    let edge_points = parallel::map(&edges, |&e| {
+        edge_point(&topology, e, &positions, &centers, &normals, creases)
+    });
+    let vertices: Vec<usize> = (0..positions.len()).collect();
+    let vertex_points = parallel::map(&vertices, |&v| {
+        vertex_point(&topology, v, &welded, &faces, &centers, &normals, creases)
+    });
+
+    let items: Vec<usize> = (0..faces.len()).collect();
+    parallel::flat_map(&items, |&q, r| {
+        let [na, nb, nc, nd] = [0, 1, 2, 3].map(|i| vertex_points[faces[q][i]]);
+        let [e1, e2, e3, e4] = [0, 1, 2, 3].map(|i| edge_points[topology.face_edges[q][i]]);
+        let center_p = centers[q];
 
         r.push(Quad {
             vertices: [na, e1, center_p, e4],
@@ -209,7 +222,5 @@ pub fn catmull_subdivide_once(quads: Vec<Quad>, creases: bool) -> Vec<Quad> {
         r.push(Quad {
             vertices: [e4, center_p, e3, nd],
         });
-    }
-
-    r
+    })
 }
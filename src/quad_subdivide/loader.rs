@@ -1,50 +1,76 @@
-use crate::quad_subdivide::{Quad, Vertex};
-
-use std::fs::File;
-use std::io::prelude::*;
-
-pub fn load_wavefront(path: &str) -> Vec<Quad> {
-    let mut file = File::open(path).expect("File not found");
-    let mut bytes: Vec<u8> = Vec::new();
-    file.read_to_end(&mut bytes).unwrap();
-
-    let mut data = ::std::io::BufReader::new(&bytes[..]);
-    let data = obj::ObjData::load_buf(&mut data).unwrap();
+use crate::mesh::{Mesh, Origin};
+use crate::quad_subdivide::{Quad, Vertex, WELD_TOLERANCE};
+use vecmath::*;
 
+/// Splits every face of the mesh into quads. Quads are kept as they are, any
+/// other polygon is cut into one quad per corner around its centroid, which is
+/// the same topology the first Catmull-Clark step would produce.
+pub fn quadrangulate(mesh: &Mesh) -> (Vec<Quad>, Vec<Origin>) {
     let mut quads = Vec::new();
+    let mut origins = Vec::new();
+    let apart = mesh.kept_apart(WELD_TOLERANCE);
 
-    for object in data.objects.iter() {
-        for polygon in object.groups.iter().flat_map(|g| g.polys.iter()) {
-            match polygon {
-                obj::SimplePolygon(indices) => {
-                    assert!(indices.len() == 4);
-                    let v1i = indices.get(0).unwrap();
-                    let v2i = indices.get(1).unwrap();
-                    let v3i = indices.get(2).unwrap();
-                    let v4i = indices.get(3).unwrap();
+    for (f, face) in mesh.faces.iter().enumerate() {
+        let vertices: Vec<Vertex> = face
+            .iter()
+            .map(|&i| Vertex {
+                position: mesh.positions[i],
+                base: apart[i],
+            })
+            .collect();
 
-                    let v1 = Vertex {
-                        position: data.position[v1i.0],
-                    };
-                    let v2 = Vertex {
-                        position: data.position[v2i.0],
-                    };
-                    let v3 = Vertex {
-                        position: data.position[v3i.0],
-                    };
-                    let v4 = Vertex {
-                        position: data.position[v4i.0],
-                    };
+        if vertices.len() == 4 {
+            quads.push(Quad {
+                vertices: [vertices[0], vertices[1], vertices[2], vertices[3]],
+            });
+            origins.push(Origin::of_corners(f, 0, 4, &[0, 1, 2, 3]));
+            continue;
+        }
+
+        let n = vertices.len();
+        let mut center = [0.0, 0.0, 0.0];
+        for v in &vertices {
+            center = vec3_add(center, v.position);
+        }
+        let center = Vertex {
+            position: vec3_scale(center, 1.0 / n as f32),
+            base: None,
+        };
 
-                    let quad = Quad {
-                        vertices: [v1, v2, v3, v4],
-                    };
+        for i in 0..n {
+            let prev = (i + n - 1) % n;
+            let next = (i + 1) % n;
+            let v = vertices[i];
+            quads.push(Quad {
+                vertices: [
+                    v,
+                    Vertex {
+                        position: vec3_scale(vec3_add(v.position, vertices[next].position), 0.5),
+                        base: None,
+                    },
+                    center,
+                    Vertex {
+                        position: vec3_scale(vec3_add(v.position, vertices[prev].position), 0.5),
+                        base: None,
+                    },
+                ],
+            });
 
-                    quads.push(quad);
-                }
-            }
+            let mut corners = vec![vec![0.0; n]; 4];
+            corners[0][i] = 1.0;
+            corners[1][i] = 0.5;
+            corners[1][next] = 0.5;
+            corners[2] = vec![1.0 / n as f32; n];
+            corners[3][i] = 0.5;
+            corners[3][prev] = 0.5;
+            origins.push(Origin {
+                face: f,
+                piece: i,
+                path: Vec::new(),
+                corners,
+            });
         }
     }
 
-    quads
+    (quads, origins)
 }
@@ -2,127 +2,156 @@ mod divider;
 mod loader;
 mod transform;
 
-use crate::render::Vertex as RVertex;
-use std::collections::{HashMap, HashSet};
-use std::hash::{Hash, Hasher};
+use crate::frame::Frame;
+use crate::mesh::{self, Mesh, Origin};
+use crate::render::Coloring;
+use std::collections::HashMap;
 use vecmath::*;
 
-#[derive(Debug, Hash)]
+#[derive(Debug, Clone)]
 pub struct Quad {
     vertices: [Vertex; 4],
 }
 
-impl Eq for Quad {}
-
-impl PartialEq for Quad {
-    fn eq(&self, other: &Self) -> bool {
-        self.vertices == other.vertices
-    }
-}
-
 #[derive(Debug, Clone, Copy)]
 pub struct Vertex {
     position: Vector3<f32>,
+    /// The base mesh vertex this is, or the vertex point of, where it must
+    /// not be welded to others at its position. See `Mesh::kept_apart`.
+    base: Option<usize>,
 }
 
-impl Eq for Vertex {}
+/// Corners closer than this are the same vertex when the quads are welded.
+const WELD_TOLERANCE: f32 = 1e-4;
 
-impl PartialEq for Vertex {
-    fn eq(&self, other: &Self) -> bool {
-        (self.position[0] - other.position[0]).abs() < 1e-4
-            && (self.position[1] - other.position[1]).abs() < 1e-4
-            && (self.position[2] - other.position[2]).abs() < 1e-4
-    }
+/// Welds the quads back into indexed positions and faces, numbering the
+/// vertices in the order the faces first use them.
+pub fn get_indexed(quads: &[Quad]) -> (Vec<[f32; 3]>, Vec<Vec<usize>>) {
+    let (vertices, faces) = weld(quads);
+    (vertices.iter().map(|v| v.position).collect(), faces)
 }
 
-impl Hash for Vertex {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        (self.position[0] as i32).hash(state);
-        (self.position[1] as i32).hash(state);
-        (self.position[2] as i32).hash(state);
-    }
+/// `get_indexed`, keeping the base vertex of every welded vertex.
+fn weld(quads: &[Quad]) -> (Vec<Vertex>, Vec<Vec<usize>>) {
+    let corners: Vec<Vertex> = quads.iter().flat_map(|t| t.vertices).collect();
+    let positions: Vec<[f32; 3]> = corners.iter().map(|v| v.position).collect();
+    let bases: Vec<Option<usize>> = corners.iter().map(|v| v.base).collect();
+    let (remap, kept) = mesh::weld_apart(&positions, &bases, WELD_TOLERANCE);
+    let faces = remap.chunks(4).map(<[usize]>::to_vec).collect();
+    (kept.iter().map(|&i| corners[i]).collect(), faces)
 }
 
-pub fn get_vertex_map(quads: &Vec<Quad>) -> HashMap<&Vertex, HashSet<&Quad>> {
-    let mut vertices: HashMap<&Vertex, HashSet<&Quad>> = HashMap::new();
-
-    for quad in quads {
-        for v in &quad.vertices {
-            if !vertices.contains_key(v) {
-                vertices.insert(&v, HashSet::new());
-            }
-            vertices.get_mut(&v).unwrap().insert(quad);
-        }
-    }
-
-    vertices
+/// The quads of every level computed so far, so changing levels in the
+/// viewer only runs the subdivision steps it has not run before.
+#[derive(Default)]
+pub struct Levels {
+    /// Where each quad of `linear[0]` lies in the base mesh.
+    origins: Vec<Origin>,
+    /// `linear[l]` is the base mesh after `l` linear levels.
+    linear: Vec<Vec<Quad>>,
+    /// `smooth[&(l, creases)][s]` is `linear[l]` after `s + 1` Catmull-Clark levels.
+    smooth: HashMap<(u32, Option<u32>), Vec<Vec<Quad>>>,
 }
 
-pub fn get_adjacent<'a>(
-    a: &'a Vertex,
-    b: &'a Vertex,
-    me: &'a Quad,
-    map: &HashMap<&'a Vertex, HashSet<&'a Quad>>,
-) -> Option<&'a Quad> {
-    map.get(&a)
-        .unwrap()
-        .intersection(map.get(&b).unwrap())
-        .find(|&&x| x != me)
-        .map(|x| *x)
-}
+impl Levels {
+    /// Checks `cancelled` between steps, giving up with `None` once it
+    /// returns true. The steps already run stay cached.
+    pub fn get_frame(
+        &mut self,
+        base: &Mesh,
+        linear_levels: u32,
+        loop_levels: u32,
+        creases: Option<f32>,
+        coloring: Coloring,
+        cancelled: &dyn Fn() -> bool,
+    ) -> Option<Frame> {
+        self.run(base, linear_levels, loop_levels, creases, cancelled)?;
+        let quads = self.level(linear_levels, loop_levels, creases);
+        // every step pushes four children per quad, in order
+        let base_faces = (coloring == Coloring::BaseFaces).then(|| {
+            let shift = 2 * (linear_levels + loop_levels);
+            (0..quads.len())
+                .map(|i| self.origins[i >> shift].face)
+                .collect::<Vec<usize>>()
+        });
+        Some(transform::transform(quads.to_vec(), base_faces.as_deref()))
+    }
 
-pub fn get_adjacency(quads: &Vec<Quad>) -> HashMap<&Quad, [Option<&Quad>; 4]> {
-    let mut map = HashMap::new();
+    /// The level as `subdivide` would return it, from the cached steps and
+    /// giving up like `get_frame` does.
+    pub fn get_mesh(
+        &mut self,
+        base: &Mesh,
+        linear_levels: u32,
+        loop_levels: u32,
+        creases: Option<f32>,
+        cancelled: &dyn Fn() -> bool,
+    ) -> Option<Mesh> {
+        self.run(base, linear_levels, loop_levels, creases, cancelled)?;
+        let (positions, faces) = get_indexed(self.level(linear_levels, loop_levels, creases));
+        let origins = divider::subdivide_origins(self.origins.clone(), linear_levels + loop_levels);
+        Some(Mesh::refined(base, positions, faces, &origins))
+    }
 
-    let mut vertices: HashMap<&Vertex, HashSet<&Quad>> = HashMap::new();
+    /// Runs the steps up to the given level that are not cached yet.
+    fn run(
+        &mut self,
+        base: &Mesh,
+        linear_levels: u32,
+        loop_levels: u32,
+        creases: Option<f32>,
+        cancelled: &dyn Fn() -> bool,
+    ) -> Option<()> {
+        if self.linear.is_empty() {
+            let (quads, origins) = loader::quadrangulate(base);
+            self.origins = origins;
+            self.linear.push(quads);
+        }
+        while self.linear.len() <= linear_levels as usize {
+            if cancelled() {
+                return None;
+            }
+            let next = divider::linear_subdivide_once(self.linear.last().unwrap().clone());
+            self.linear.push(next);
+        }
 
-    for quad in quads {
-        for v in &quad.vertices {
-            if !vertices.contains_key(v) {
-                vertices.insert(&v, HashSet::new());
+        let linear = &self.linear[linear_levels as usize];
+        let smooth = self
+            .smooth
+            .entry((linear_levels, creases.map(f32::to_bits)))
+            .or_default();
+        while smooth.len() < loop_levels as usize {
+            if cancelled() {
+                return None;
             }
-            vertices.get_mut(&v).unwrap().insert(quad);
+            let next = divider::catmull_subdivide_once(smooth.last().unwrap_or(linear).clone(), creases);
+            smooth.push(next);
         }
+        Some(())
     }
 
-    for quad in quads {
-        let v1 = quad.vertices[0];
-        let v2 = quad.vertices[1];
-        let v3 = quad.vertices[2];
-        let v4 = quad.vertices[3];
-        let n1 = vertices
-            .get(&v1)
-            .unwrap()
-            .intersection(vertices.get(&v2).unwrap())
-            .find(|&&x| x != quad)
-            .map(|x| *x);
-        let n2 = vertices
-            .get(&v2)
-            .unwrap()
-            .intersection(vertices.get(&v3).unwrap())
-            .find(|&&x| x != quad)
-            .map(|x| *x);
-        let n3 = vertices
-            .get(&v3)
-            .unwrap()
-            .intersection(vertices.get(&v4).unwrap())
-            .find(|&&x| x != quad)
-            .map(|x| *x);
-        let n4 = vertices
-            .get(&v4)
-            .unwrap()
-            .intersection(vertices.get(&v1).unwrap())
-            .find(|&&x| x != quad)
-            .map(|x| *x);
-        map.insert(quad, [n1, n2, n3, n4]);
+    /// A level `run` has computed.
+    fn level(&self, linear_levels: u32, loop_levels: u32, creases: Option<f32>) -> &[Quad] {
+        match loop_levels {
+            0 => &self.linear[linear_levels as usize],
+            n => &self.smooth[&(linear_levels, creases.map(f32::to_bits))][n as usize - 1],
+        }
     }
-
-    map
 }
 
-pub fn get_vertices(path: &str, linear_levels: u32, loop_levels: u32, creases: bool) -> Vec<RVertex> {
-    let mut quads = loader::load_wavefront(path);
+/// Runs the same pipeline as `Levels::get_frame` but returns an indexed mesh in the
+/// original coordinate frame.
+pub fn subdivide(base: &Mesh, linear_levels: u32, loop_levels: u32, creases: Option<f32>) -> Mesh {
+    let (mut quads, origins) = loader::quadrangulate(base);
     quads = divider::linear_subdivide(quads, linear_levels);
     quads = divider::catmull_subdivide(quads, loop_levels, creases);
-    transform::transform(quads)
+    let origins = divider::subdivide_origins(origins, linear_levels + loop_levels);
+    let (positions, faces) = get_indexed(&quads);
+    Mesh::refined(base, positions, faces, &origins)
+}
+
+/// Which base face, and where in it, each face of `subdivide`'s output comes
+/// from.
+pub fn origins(base: &Mesh, levels: u32) -> Vec<Origin> {
+    divider::subdivide_origins(loader::quadrangulate(base).1, levels)
 }
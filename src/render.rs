@@ -1,33 +1,141 @@
-use glium::vertex::VertexBufferAny;
+use glium::index::{IndexBuffer, IndicesSource, NoIndices, PrimitiveType};
+use glium::vertex::{VertexBuffer, VerticesSource};
 use glium::{self, implement_vertex};
 use glium::{glutin, program, uniform, Surface};
 
 use crate::camera;
-use crate::quad_subdivide;
+use crate::frame::{self, Frame};
+use crate::mesh::{self, Mesh};
+use crate::ptex::FaceTextures;
+use crate::scheme::{self, Cache, Scheme};
 use crate::shaders;
 use crate::support;
-use crate::triangle_subdivide;
+use crate::worker::{Export, Worker};
 
+use std::error::Error;
+use std::fs;
+use std::time::{Duration, Instant};
 
 #[derive(Copy, Clone, Debug)]
 pub struct Vertex {
     pub position: [f32; 3],
     pub color: [f32; 3],
+    /// Unit normal for lighting, smoothed across the faces sharing the
+    /// vertex.
+    pub normal: [f32; 3],
+    /// Where the vertex samples the per-face texture atlas, if there is one.
+    pub tex_coords: [f32; 2],
 }
 
-implement_vertex!(Vertex, position, color);
+implement_vertex!(Vertex, position, color, normal, tex_coords);
 
-pub fn render(path: String, quads: bool, mut creases: bool) {
-    let vertex_data = if quads {
-        quad_subdivide::get_vertices(&path, 0, 0, creases)
-    } else {
-        triangle_subdivide::get_vertices(&path, 0, 0, creases)
+/// A vertex of the control cage overlay.
+#[derive(Copy, Clone, Debug)]
+pub struct LineVertex {
+    pub position: [f32; 3],
+}
+
+implement_vertex!(LineVertex, position);
+
+/// How the viewer colours meshes that have no vertex colours of their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Coloring {
+    /// Neighbouring faces get different colours.
+    Faces,
+    /// Every face gets the colour of the base face it came from.
+    BaseFaces,
+}
+
+/// How the viewer lights the mesh, numbered as the fragment shader's
+/// `shading` uniform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shading {
+    /// Face colours only, without lighting.
+    Flat = 0,
+    /// Grey Lambert and Phong lighting, to judge the shape of the surface.
+    Lit = 1,
+    /// Face colours multiplied by the lighting.
+    Colored = 2,
+}
+
+impl Shading {
+    /// The next mode in the order the hotkey cycles through them.
+    fn next(self) -> Shading {
+        match self {
+            Shading::Flat => Shading::Lit,
+            Shading::Lit => Shading::Colored,
+            Shading::Colored => Shading::Flat,
+        }
+    }
+}
+
+/// A colour for base face `face`, stepping the hue by the golden ratio so
+/// that faces with nearby indices look different.
+pub fn base_face_color(face: usize) -> [f32; 3] {
+    let hue = (face as f32 * 0.618_034).fract() * 6.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let [r, g, b] = match hue as u32 {
+        0 => [1.0, x, 0.0],
+        1 => [x, 1.0, 0.0],
+        2 => [0.0, 1.0, x],
+        3 => [0.0, x, 1.0],
+        4 => [x, 0.0, 1.0],
+        _ => [1.0, 0.0, x],
     };
+    // soften towards white so that the colours are not too saturated
+    [0.3 + 0.65 * r, 0.3 + 0.65 * g, 0.3 + 0.65 * b]
+}
+
+/// Loads the mesh again for `--watch`, picking the scheme to use for it.
+pub type Reload = Box<dyn Fn() -> Result<(Mesh, Scheme), Box<dyn Error>>>;
+
+/// How often the watched file's modification time is checked.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Colour of the edges of the level on screen.
+const WIREFRAME_COLOR: [f32; 3] = [0.1, 0.1, 0.1];
+
+/// Colour of the control cage's edges and vertices.
+const CAGE_COLOR: [f32; 3] = [1.0, 0.45, 0.0];
+
+/// Diameter in pixels of the control cage's vertices.
+const CAGE_POINT_SIZE: f32 = 6.0;
+
+/// Opens the viewer on `base`, which was loaded from `path`. `crease_cosine`
+/// is the threshold used while crease detection is switched on. With `watch`,
+/// the file is reloaded whenever it changes on disk, and load errors are shown
+/// in the title bar while the last good mesh stays on screen. Subdivision runs
+/// on a `Worker` thread, and the previous level is drawn until it finishes.
+/// `textures` are drawn on the mesh while it is subdivided with Catmull-Clark.
+pub fn render(
+    path: String,
+    base: Mesh,
+    scheme: Scheme,
+    creases: bool,
+    crease_cosine: f32,
+    textures: Option<FaceTextures>,
+    watch: Option<Reload>,
+) -> ! {
+    let mut scheme = scheme;
+    let mut creases = creases.then_some(crease_cosine);
+    let mut coloring = Coloring::Faces;
+    let mut shading = Shading::Flat;
+    let mut faceted = false;
+    let mut wireframe = false;
+    let mut show_cage = false;
+    let mut cache = scheme::Cache::new(base.clone(), textures.clone());
+    let frame = cache
+        .get_frame(scheme, 0, 0, creases, coloring, &|| false)
+        .unwrap()
+        .clone();
+    let vertex_data = &frame.vertices;
+    let mut worker = Worker::spawn(cache);
+    let mut load_error = None;
 
     let event_loop = glutin::event_loop::EventLoop::new();
     let wb = glutin::window::WindowBuilder::new()
         .with_inner_size(glium::glutin::dpi::LogicalSize::new(1024.0, 768.0))
-        .with_title("CS354H Final Project");
+        .with_title(title(scheme, None, None));
 
     let cb = glutin::ContextBuilder::new().with_depth_buffer(24);
     let display = glium::Display::new(wb, cb, &event_loop).unwrap();
@@ -35,8 +143,28 @@ pub fn render(path: String, quads: bool, mut creases: bool) {
     let program = program!(&display,
         140 => {
             vertex: shaders::VERTEX_SHADER,
-            fragment: shaders::FRAGMENT_SHADER_FLAT,
-            // fragment: shaders::FRAGMENT_SHADER_SHADED,
+            fragment: shaders::FRAGMENT_SHADER,
+        },
+    )
+    .unwrap();
+
+    // the atlas, or a white texel to bind while there is none
+    let atlas = match &textures {
+        Some(textures) => {
+            let dimensions = textures.atlas.dimensions();
+            glium::texture::RawImage2d::from_raw_rgba_reversed(
+                &textures.atlas.clone().into_raw(),
+                dimensions,
+            )
+        }
+        None => glium::texture::RawImage2d::from_raw_rgba(vec![255u8; 4], (1, 1)),
+    };
+    let atlas = glium::texture::SrgbTexture2d::new(&display, atlas).unwrap();
+
+    let line_program = program!(&display,
+        140 => {
+            vertex: shaders::LINE_VERTEX_SHADER,
+            fragment: shaders::LINE_FRAGMENT_SHADER,
         },
     )
     .unwrap();
@@ -71,10 +199,12 @@ pub fn render(path: String, quads: bool, mut creases: bool) {
     let mut linear_levels = 0;
     let mut loop_levels = 0;
 
-    let mut vertex_buffer: VertexBufferAny =
-        glium::vertex::VertexBuffer::new(&display, &vertex_data)
-            .unwrap()
-            .into();
+    let modified = |path: &str| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut last_modified = modified(&path);
+    let mut last_check = Instant::now();
+
+    let mut buffers = Buffers::new(&display, &frame);
+    let mut cage = Cage::new(&display, &base);
 
     support::start_loop(event_loop, move |events| {
         camera.update();
@@ -82,6 +212,11 @@ pub fn render(path: String, quads: bool, mut creases: bool) {
         let uniforms = uniform! {
             persp_matrix: camera.get_perspective(),
             view_matrix: camera.get_view(),
+            tex: &atlas,
+            textured: textures.is_some() && scheme == Scheme::CatmullClark,
+            shading: shading as i32,
+            faceted: faceted,
+            face_colors: buffers.face_colors,
         };
 
         let params = glium::DrawParameters {
@@ -90,6 +225,16 @@ pub fn render(path: String, quads: bool, mut creases: bool) {
                 write: true,
                 ..Default::default()
             },
+            // push the faces back so the overlays on them are not hidden
+            polygon_offset: glium::draw_parameters::PolygonOffset {
+                factor: 1.0,
+                units: 1.0,
+                fill: true,
+                ..Default::default()
+            },
+            point_size: Some(CAGE_POINT_SIZE),
+            // each face's triangles end on the corner holding its colour
+            provoking_vertex: glium::draw_parameters::ProvokingVertex::LastVertex,
             ..Default::default()
         };
 
@@ -97,32 +242,94 @@ pub fn render(path: String, quads: bool, mut creases: bool) {
         target.clear_color_and_depth((1.0, 1.0, 1.0, 1.0), 1.0);
         target
             .draw(
-                &vertex_buffer,
-                &glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
+                &buffers.vertices,
+                &buffers.triangles,
                 &program,
                 &uniforms,
                 &params,
             )
             .unwrap();
+        let mut overlay = |vertices: VerticesSource,
+                           indices: IndicesSource,
+                           offset: [f32; 3],
+                           color: [f32; 3]| {
+            let uniforms = uniform! {
+                persp_matrix: camera.get_perspective(),
+                view_matrix: camera.get_view(),
+                offset: offset,
+                color: color,
+            };
+            target
+                .draw(vertices, indices, &line_program, &uniforms, &params)
+                .unwrap();
+        };
+        if wireframe {
+            overlay(
+                (&buffers.vertices).into(),
+                (&buffers.edges).into(),
+                [0.0; 3],
+                WIREFRAME_COLOR,
+            );
+        }
+        if show_cage {
+            overlay(
+                (&cage.vertices).into(),
+                (&cage.edges).into(),
+                buffers.center,
+                CAGE_COLOR,
+            );
+            overlay(
+                (&cage.vertices).into(),
+                NoIndices(PrimitiveType::Points).into(),
+                buffers.center,
+                CAGE_COLOR,
+            );
+        }
         target.finish().unwrap();
 
         let mut action = support::Action::Continue;
 
         let mut changed = false;
+        let mut title_changed = false;
 
         for event in events {
-            match event {
-                glutin::event::Event::WindowEvent { event, .. } => match event {
+            if let glutin::event::Event::WindowEvent { event, .. } = event {
+                match event {
                     glutin::event::WindowEvent::CloseRequested => action = support::Action::Stop,
                     glutin::event::WindowEvent::KeyboardInput { input, .. } => {
                         let pressed = input.state == glutin::event::ElementState::Pressed;
-                        camera.process_input(&input);
+                        camera.process_input(input);
                         let o1 = linear_levels;
                         let o2 = loop_levels;
                         let o3 = creases;
+                        let o4 = coloring;
                         if pressed {
                             if let Some(key) = input.virtual_keycode {
                                 match key {
+                                    glutin::event::VirtualKeyCode::E => worker.export(
+                                        Export::Mesh,
+                                        &path,
+                                        scheme,
+                                        linear_levels,
+                                        loop_levels,
+                                        creases,
+                                    ),
+                                    glutin::event::VirtualKeyCode::U => worker.export(
+                                        Export::Cage,
+                                        &path,
+                                        scheme,
+                                        linear_levels,
+                                        loop_levels,
+                                        creases,
+                                    ),
+                                    glutin::event::VirtualKeyCode::L => worker.export(
+                                        Export::Levels,
+                                        &path,
+                                        scheme,
+                                        linear_levels,
+                                        loop_levels,
+                                        creases,
+                                    ),
                                     glutin::event::VirtualKeyCode::Key0 => linear_levels = 0,
                                     glutin::event::VirtualKeyCode::Key1 => linear_levels = 1,
                                     glutin::event::VirtualKeyCode::Key2 => linear_levels = 2,
@@ -131,31 +338,215 @@ pub fn render(path: String, quads: bool, mut creases: bool) {
                                     glutin::event::VirtualKeyCode::Key6 => loop_levels = 1,
                                     glutin::event::VirtualKeyCode::Key7 => loop_levels = 2,
                                     glutin::event::VirtualKeyCode::Key8 => loop_levels = 3,
-                                    glutin::event::VirtualKeyCode::C => creases = !creases,
+                                    glutin::event::VirtualKeyCode::C => {
+                                        creases = match creases {
+                                            Some(_) => None,
+                                            None => Some(crease_cosine),
+                                        }
+                                    }
+                                    glutin::event::VirtualKeyCode::B => {
+                                        coloring = match coloring {
+                                            Coloring::Faces => Coloring::BaseFaces,
+                                            Coloring::BaseFaces => Coloring::Faces,
+                                        }
+                                    }
+                                    glutin::event::VirtualKeyCode::H => shading = shading.next(),
+                                    glutin::event::VirtualKeyCode::F => faceted = !faceted,
+                                    glutin::event::VirtualKeyCode::X => wireframe = !wireframe,
+                                    glutin::event::VirtualKeyCode::K => show_cage = !show_cage,
                                     _ => (),
                                 }
                             }
                         }
-                        changed = o1 != linear_levels || o2 != loop_levels || o3 != creases;
+                        changed |= o1 != linear_levels
+                            || o2 != loop_levels
+                            || o3 != creases
+                            || o4 != coloring;
                     }
                     _ => (),
-                },
-                _ => (),
+                }
+            }
+        }
+
+        if let Some(reload) = &watch {
+            if last_check.elapsed() >= WATCH_INTERVAL {
+                last_check = Instant::now();
+                let now = modified(&path);
+                if now != last_modified {
+                    last_modified = now;
+                    // the textures were loaded for the first mesh's Ptex faces
+                    let reloaded = reload().and_then(|(mesh, s)| {
+                        if let Some(textures) = &textures {
+                            textures.check(&mesh)?;
+                        }
+                        Ok((mesh, s))
+                    });
+                    match reloaded {
+                        Ok((mesh, s)) => {
+                            cage = Cage::new(&display, &mesh);
+                            worker.reload(mesh);
+                            scheme = s;
+                            load_error = None;
+                            changed = true;
+                        }
+                        Err(e) => load_error = Some(format!("{}: {}", path, e)),
+                    }
+                    title_changed = true;
+                }
             }
         }
 
         if changed {
-            let vertex_data = if quads {
-                quad_subdivide::get_vertices(&path, linear_levels, loop_levels, creases)
-            } else {
-                triangle_subdivide::get_vertices(&path, linear_levels, loop_levels, creases)
-            };
-            vertex_buffer = glium::vertex::VertexBuffer::new(&display, &vertex_data)
-                .unwrap()
-                .into();
+            worker.request(scheme, linear_levels, loop_levels, creases, coloring);
+            title_changed = true;
         }
 
+        if let Some(frame) = worker.poll() {
+            buffers = Buffers::new(&display, &frame);
+            title_changed = true;
+        }
+
+        if title_changed {
+            let computing = worker.is_busy().then_some(loop_levels);
+            display.gl_window().window().set_title(&title(
+                scheme,
+                load_error.as_deref(),
+                computing,
+            ));
+        }
 
         action
     });
 }
+
+/// A `Frame` uploaded to the GPU.
+struct Buffers {
+    vertices: VertexBuffer<Vertex>,
+    triangles: IndexBuffer<u32>,
+    edges: IndexBuffer<u32>,
+    center: [f32; 3],
+    face_colors: bool,
+}
+
+impl Buffers {
+    fn new(display: &glium::Display, frame: &Frame) -> Buffers {
+        Buffers {
+            vertices: VertexBuffer::new(display, &frame.vertices).unwrap(),
+            triangles: IndexBuffer::new(display, PrimitiveType::TrianglesList, &frame.triangles)
+                .unwrap(),
+            edges: IndexBuffer::new(display, PrimitiveType::LinesList, &frame.edges).unwrap(),
+            center: frame.center,
+            face_colors: frame.face_colors,
+        }
+    }
+}
+
+/// The vertices and edges of the control cage, in the coordinates of the
+/// file.
+struct Cage {
+    vertices: VertexBuffer<LineVertex>,
+    edges: IndexBuffer<u32>,
+}
+
+impl Cage {
+    fn new(display: &glium::Display, base: &Mesh) -> Cage {
+        let vertices: Vec<LineVertex> = base
+            .positions
+            .iter()
+            .map(|&position| LineVertex { position })
+            .collect();
+        Cage {
+            vertices: VertexBuffer::new(display, &vertices).unwrap(),
+            edges: IndexBuffer::new(
+                display,
+                PrimitiveType::LinesList,
+                &frame::edges(&base.faces),
+            )
+            .unwrap(),
+        }
+    }
+}
+
+/// The window title: the scheme, then a load error from `--watch` or the
+/// level being computed, if any.
+fn title(scheme: Scheme, error: Option<&str>, computing: Option<u32>) -> String {
+    let mut title = format!("CS354H Final Project - {}", scheme);
+    if let Some(error) = error {
+        title += &format!(" - {}", error);
+    }
+    if let Some(level) = computing {
+        title += &format!(" - computing level {}…", level);
+    }
+    title
+}
+
+/// Writes the mesh currently on screen next to the input file as
+/// `<name>_subdivided.<ext>`, in the same format as the input. Textured
+/// meshes get their atlas coordinates, and the atlas is written beside them.
+pub fn export(
+    cache: &mut Cache,
+    path: &str,
+    scheme: Scheme,
+    linear_levels: u32,
+    loop_levels: u32,
+    creases: Option<f32>,
+) {
+    let mut subdivided = cache.get_mesh(scheme, linear_levels, loop_levels, creases);
+    let (stem, ext) = path.rsplit_once('.').unwrap();
+    let out = format!("{}_subdivided.{}", stem, ext);
+    let textures = cache.textures.as_ref();
+    if let Some(textures) = textures.filter(|_| scheme == Scheme::CatmullClark) {
+        subdivided = textures.apply(&cache.base, &subdivided);
+        match textures.save_atlas(&out) {
+            Ok(atlas) => println!("Wrote {}", atlas),
+            Err(e) => println!("Could not write the atlas for {}: {}", out, e),
+        }
+    }
+    match mesh::save(&subdivided, &out) {
+        Ok(()) => println!("Wrote {}", out),
+        Err(e) => println!("Could not write {}: {}", out, e),
+    }
+}
+
+/// Writes every level from 0 up to the one on screen into
+/// `<name>_levels.glb`, one glTF mesh per level.
+pub fn export_levels(
+    cache: &mut Cache,
+    path: &str,
+    scheme: Scheme,
+    linear_levels: u32,
+    loop_levels: u32,
+    creases: Option<f32>,
+) {
+    let levels: Vec<Mesh> = (0..=loop_levels)
+        .map(|level| cache.get_mesh(scheme, linear_levels, level, creases))
+        .collect();
+    let (stem, _) = path.rsplit_once('.').unwrap();
+    let out = format!("{}_levels.glb", stem);
+    let options = mesh::GltfOptions {
+        binary: true,
+        ..mesh::GltfOptions::default()
+    };
+    match mesh::write_gltf(&levels, &out, &options) {
+        Ok(()) => println!("Wrote {}", out),
+        Err(e) => println!("Could not write {}: {}", out, e),
+    }
+}
+
+/// Writes the control cage, after the linear levels on screen, to
+/// `<name>.usda` for renderers that subdivide natively.
+pub fn export_cage(
+    cache: &mut Cache,
+    path: &str,
+    scheme: Scheme,
+    linear_levels: u32,
+    creases: Option<f32>,
+) {
+    let cage = cache.get_mesh(scheme, linear_levels, 0, creases);
+    let (stem, _) = path.rsplit_once('.').unwrap();
+    let out = format!("{}.usda", stem);
+    match mesh::write_usda(&cage, &out, scheme, creases) {
+        Ok(()) => println!("Wrote {}", out),
+        Err(e) => println!("Could not write {}: {}", out, e),
+    }
+}
@@ -0,0 +1,431 @@
+use crate::mesh::Mesh;
+use crate::validate;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use vecmath::*;
+
+/// What `repair` should do besides the clean-up it always runs.
+#[derive(Debug, Clone, Copy)]
+pub struct RepairOptions {
+    /// Distance below which vertices are welded into one.
+    pub weld: f32,
+    /// Fill boundary loops with at most this many edges.
+    pub fill_holes: Option<usize>,
+}
+
+/// Counts of what `repair` changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Repairs {
+    pub welded_vertices: usize,
+    pub split_vertices: usize,
+    pub removed_faces: usize,
+    pub flipped_faces: usize,
+    pub filled_holes: usize,
+}
+
+impl Repairs {
+    pub fn is_empty(&self) -> bool {
+        *self == Repairs::default()
+    }
+}
+
+impl fmt::Display for Repairs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "welded {} vertices, split {} non-manifold vertices, removed {} duplicate or degenerate faces, flipped {} faces, filled {} holes",
+            self.welded_vertices,
+            self.split_vertices,
+            self.removed_faces,
+            self.flipped_faces,
+            self.filled_holes
+        )
+    }
+}
+
+/// Cleans up a mesh so the subdividers can run on it: welds near-coincident
+/// vertices, drops duplicate and degenerate faces, gives each fan of faces
+/// meeting at a non-manifold vertex its own copy of the vertex, makes the winding agree
+/// across each connected component (outwards for closed ones) and optionally
+/// fills small holes. Non-manifold edges are left alone.
+pub fn repair(mesh: &mut Mesh, options: &RepairOptions) -> Repairs {
+    let mut repairs = Repairs::default();
+
+    let (vertices, faces) = (mesh.positions.len(), mesh.faces.len());
+    mesh.weld(options.weld);
+    repairs.welded_vertices = vertices - mesh.positions.len();
+
+    let positions = &mesh.positions;
+    let keep: Vec<bool> = mesh
+        .faces
+        .iter()
+        .map(|face| {
+            let repeated = (0..face.len()).any(|i| face[..i].contains(&face[i]));
+            !repeated && !validate::has_zero_area(positions, face)
+        })
+        .collect();
+    retain_faces(mesh, &keep);
+    repairs.removed_faces = faces - mesh.faces.len();
+    repairs.split_vertices = split_vertices(mesh);
+
+    let mut flipped = orient(mesh);
+    if let Some(max_edges) = options.fill_holes {
+        repairs.filled_holes = fill_holes(mesh, max_edges);
+        // components closed by the new faces may still face inwards
+        if repairs.filled_holes > 0 {
+            for (flipped, again) in flipped.iter_mut().zip(orient(mesh)) {
+                *flipped ^= again;
+            }
+        }
+    }
+    repairs.flipped_faces = flipped.iter().filter(|&&f| f).count();
+    repairs
+}
+
+/// Runs `repair` if there are options for it and prints what it changed.
+pub fn check(mesh: &mut Mesh, options: Option<RepairOptions>) {
+    if let Some(options) = options {
+        let repairs = repair(mesh, &options);
+        if !repairs.is_empty() {
+            eprintln!("repaired: {}", repairs);
+        }
+    }
+}
+
+fn retain_faces(mesh: &mut Mesh, keep: &[bool]) {
+    let mut f = 0;
+    mesh.faces.retain(|_| {
+        f += 1;
+        keep[f - 1]
+    });
+    if !mesh.face_groups.is_empty() {
+        let mut f = 0;
+        mesh.face_groups.retain(|_| {
+            f += 1;
+            keep[f - 1]
+        });
+    }
+    if !mesh.origins.is_empty() {
+        let mut f = 0;
+        mesh.origins.retain(|_| {
+            f += 1;
+            keep[f - 1]
+        });
+    }
+}
+
+/// Copies every vertex whose faces form more than one fan, so each fan gets
+/// its own vertex. Returns how many vertices were split.
+fn split_vertices(mesh: &mut Mesh) -> usize {
+    let mut vertex_faces = vec![Vec::new(); mesh.positions.len()];
+    for (f, face) in mesh.faces.iter().enumerate() {
+        for &v in face {
+            vertex_faces[v].push(f);
+        }
+    }
+
+    let mut split = 0;
+    for (v, faces) in vertex_faces.iter().enumerate() {
+        let fans = validate::fans(mesh, v, faces);
+        if fans.len() < 2 {
+            continue;
+        }
+        for fan in &fans[1..] {
+            let copy = copy_vertex(mesh, v);
+            for &f in fan {
+                for corner in mesh.faces[f].iter_mut().filter(|c| **c == v) {
+                    *corner = copy;
+                }
+            }
+        }
+        split += 1;
+    }
+    split
+}
+
+/// Appends a copy of vertex `v` with all its attributes, returning its index.
+fn copy_vertex(mesh: &mut Mesh, v: usize) -> usize {
+    mesh.positions.push(mesh.positions[v]);
+    if let Some(normals) = &mut mesh.normals {
+        normals.push(normals[v]);
+    }
+    if let Some(uvs) = &mut mesh.uvs {
+        uvs.push(uvs[v]);
+    }
+    if let Some(colors) = &mut mesh.colors {
+        colors.push(colors[v]);
+    }
+    for property in &mut mesh.properties {
+        property.values.push(property.values[v]);
+    }
+    mesh.positions.len() - 1
+}
+
+/// Reverses the winding of a face, keeping its first corner.
+fn flip(face: &mut [usize]) {
+    face[1..].reverse();
+}
+
+/// Walks each component across its manifold edges, flipping faces to agree
+/// with the first face reached. Returns which faces were flipped.
+fn orient(mesh: &mut Mesh) -> Vec<bool> {
+    // undirected edge -> (face, whether the face runs from the lower index)
+    let mut edges: HashMap<[usize; 2], Vec<(usize, bool)>> = HashMap::new();
+    for (f, face) in mesh.faces.iter().enumerate() {
+        for i in 0..face.len() {
+            let a = face[i];
+            let b = face[(i + 1) % face.len()];
+            edges
+                .entry([a.min(b), a.max(b)])
+                .or_default()
+                .push((f, a < b));
+        }
+    }
+
+    let mut flipped = vec![false; mesh.faces.len()];
+    let mut visited = vec![false; mesh.faces.len()];
+    for start in 0..mesh.faces.len() {
+        if visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let mut component = vec![start];
+        let mut closed = true;
+        let mut queue = VecDeque::from([start]);
+        while let Some(f) = queue.pop_front() {
+            let face = &mesh.faces[f];
+            for i in 0..face.len() {
+                let a = face[i];
+                let b = face[(i + 1) % face.len()];
+                let half_edges = &edges[&[a.min(b), a.max(b)]];
+                if half_edges.len() == 1 {
+                    closed = false;
+                }
+                if half_edges.len() != 2 {
+                    continue;
+                }
+                let forward = (a < b) != flipped[f];
+                for &(g, g_forward) in half_edges {
+                    if !visited[g] {
+                        visited[g] = true;
+                        flipped[g] = g_forward == forward;
+                        component.push(g);
+                        queue.push_back(g);
+                    }
+                }
+            }
+        }
+
+        if closed {
+            // measured from a corner of the component rather than the origin,
+            // which would cancel most of the precision away from it
+            let origin = mesh.positions[mesh.faces[start][0]];
+            let at = |v: usize| vec3_sub(mesh.positions[v], origin);
+            let mut volume = 0.0;
+            for &f in &component {
+                let face = &mesh.faces[f];
+                let a = at(face[0]);
+                for i in 1..face.len() - 1 {
+                    let b = at(face[i]);
+                    let c = at(face[i + 1]);
+                    let v = vec3_dot(a, vec3_cross(b, c));
+                    volume += if flipped[f] { -v } else { v };
+                }
+            }
+            if volume < 0.0 {
+                for &f in &component {
+                    flipped[f] = !flipped[f];
+                }
+            }
+        }
+    }
+
+    for (face, &flipped) in mesh.faces.iter_mut().zip(&flipped) {
+        if flipped {
+            flip(face);
+        }
+    }
+    flipped
+}
+
+/// Closes every boundary loop with at most `max_edges` edges. Triangle meshes
+/// get a fan of triangles so they stay triangle meshes, others get a single
+/// polygon. Returns how many holes were filled.
+fn fill_holes(mesh: &mut Mesh, max_edges: usize) -> usize {
+    let mut half_edges = HashMap::new();
+    for (f, face) in mesh.faces.iter().enumerate() {
+        for i in 0..face.len() {
+            half_edges.insert((face[i], face[(i + 1) % face.len()]), f);
+        }
+    }
+
+    // boundary half edges, from a vertex to the next one along the hole
+    let mut next: HashMap<usize, Option<usize>> = HashMap::new();
+    for &(a, b) in half_edges.keys() {
+        if !half_edges.contains_key(&(b, a)) {
+            // two boundary edges leaving one vertex make the loops ambiguous
+            next.entry(b).and_modify(|n| *n = None).or_insert(Some(a));
+        }
+    }
+
+    let triangles = mesh.is_triangles();
+    let mut starts: Vec<usize> = next.keys().copied().collect();
+    starts.sort_unstable();
+    let mut filled = 0;
+    for start in starts {
+        let mut hole = vec![start];
+        let mut v = start;
+        let closed = loop {
+            match next.get(&v).copied().flatten() {
+                Some(n) if n == start => break true,
+                Some(n) if hole.len() < max_edges && !hole.contains(&n) => {
+                    hole.push(n);
+                    v = n;
+                }
+                _ => break false,
+            }
+        };
+        // only fill each loop once, from its lowest vertex
+        if !closed || hole.len() < 3 || hole.iter().any(|&v| v < start) {
+            continue;
+        }
+
+        let group = half_edges[&(hole[1], hole[0])];
+        let new_faces: Vec<Vec<usize>> = if triangles {
+            (1..hole.len() - 1)
+                .map(|i| vec![hole[0], hole[i], hole[i + 1]])
+                .collect()
+        } else {
+            vec![hole]
+        };
+        for face in new_faces {
+            mesh.faces.push(face);
+            if !mesh.face_groups.is_empty() {
+                mesh.face_groups.push(mesh.face_groups[group]);
+            }
+        }
+        filled += 1;
+    }
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheme::{self, Scheme};
+
+    const OPTIONS: RepairOptions = RepairOptions {
+        weld: 1e-4,
+        fill_holes: None,
+    };
+
+    #[test]
+    fn keeps_small_faces_far_from_the_origin() {
+        let offset = [1e4, 2e4, 3e3];
+        let mut mesh = Mesh {
+            positions: [
+                [0.0, 0.0, 0.0],
+                [0.1, 0.0, 0.0],
+                [0.0, 0.1, 0.0],
+                [0.0, 0.0, 0.1],
+            ]
+            .iter()
+            .map(|&c| vec3_add(offset, c))
+            .collect(),
+            // one face wound the wrong way
+            faces: vec![vec![0, 2, 1], vec![0, 1, 3], vec![0, 2, 3], vec![1, 2, 3]],
+            ..Mesh::default()
+        };
+        let repairs = repair(
+            &mut mesh,
+            &RepairOptions {
+                weld: 1e-4,
+                fill_holes: None,
+            },
+        );
+        assert_eq!(repairs.removed_faces, 0);
+        assert_eq!(repairs.flipped_faces, 1);
+        assert_eq!(mesh.faces.len(), 4);
+        assert!(validate::validate(&mesh).is_empty());
+    }
+
+    #[test]
+    fn removes_duplicate_and_degenerate_faces() {
+        let mut mesh = crate::mesh::load("assets/quad_cube.obj").unwrap();
+        mesh.faces.push(mesh.faces[2].clone());
+        // the same face, starting from another corner and wound the other way
+        let mut reversed = mesh.faces[4].clone();
+        reversed.reverse();
+        mesh.faces.push(reversed);
+        mesh.faces.push(vec![0, 1, 0]);
+        let repairs = repair(&mut mesh, &OPTIONS);
+        assert_eq!(repairs.removed_faces, 3);
+        assert_eq!(mesh.faces.len(), 6);
+        assert!(validate::validate(&mesh).is_empty());
+    }
+
+    #[test]
+    fn fills_holes_up_to_the_given_size() {
+        let mut open = crate::mesh::load("assets/quad_cube.obj").unwrap();
+        open.faces.remove(3);
+
+        let mut mesh = open.clone();
+        let small = RepairOptions {
+            fill_holes: Some(3),
+            ..OPTIONS
+        };
+        assert_eq!(repair(&mut mesh, &small).filled_holes, 0);
+        assert_eq!(mesh.faces.len(), 5);
+
+        let mut mesh = open;
+        let options = RepairOptions {
+            fill_holes: Some(4),
+            ..OPTIONS
+        };
+        let repairs = repair(&mut mesh, &options);
+        assert_eq!(repairs.filled_holes, 1);
+        assert_eq!(repairs.flipped_faces, 0);
+        assert_eq!(mesh.faces.len(), 6);
+        assert!(validate::validate(&mesh).is_empty());
+    }
+
+    #[test]
+    fn pinched_vertices_stay_split_when_subdivided() {
+        // two tetrahedra touching at their tips
+        let mut mesh = Mesh {
+            positions: vec![
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 1.0],
+                [-0.5, 0.9, 1.0],
+                [-0.5, -0.9, 1.0],
+                [1.0, 0.0, -1.0],
+                [-0.5, 0.9, -1.0],
+                [-0.5, -0.9, -1.0],
+            ],
+            faces: vec![
+                vec![0, 2, 1],
+                vec![0, 3, 2],
+                vec![0, 1, 3],
+                vec![1, 2, 3],
+                vec![0, 4, 5],
+                vec![0, 5, 6],
+                vec![0, 6, 4],
+                vec![4, 6, 5],
+            ],
+            ..Mesh::default()
+        };
+        assert!(!validate::validate(&mesh).is_empty());
+        assert_eq!(repair(&mut mesh, &OPTIONS).split_vertices, 1);
+        assert_eq!(mesh.positions.len(), 8);
+
+        for (scheme, linear) in [
+            (Scheme::Loop, 0),
+            (Scheme::Loop, 1),
+            (Scheme::CatmullClark, 1),
+        ] {
+            let refined = scheme::subdivide(scheme, &mesh, linear, 2, None);
+            assert_eq!(validate::validate(&refined), Vec::new(), "{:?}", scheme);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use crate::frame::Frame;
+use crate::mesh::Mesh;
+use crate::ptex::FaceTextures;
+use crate::quad_subdivide;
+use crate::render::Coloring;
+use crate::triangle_subdivide;
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// The smoothing rules used for the non-linear subdivision levels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum Scheme {
+    Loop,
+    #[value(alias = "catmull")]
+    CatmullClark,
+}
+
+impl Scheme {
+    /// Loop for meshes made only of triangles, Catmull-Clark for quads or
+    /// mixed polygons.
+    pub fn detect(mesh: &Mesh) -> Scheme {
+        if mesh.is_triangles() {
+            Scheme::Loop
+        } else {
+            Scheme::CatmullClark
+        }
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scheme::Loop => write!(f, "Loop"),
+            Scheme::CatmullClark => write!(f, "Catmull-Clark"),
+        }
+    }
+}
+
+pub fn subdivide(
+    scheme: Scheme,
+    mesh: &Mesh,
+    linear_levels: u32,
+    levels: u32,
+    creases: Option<f32>,
+) -> Mesh {
+    match scheme {
+        Scheme::Loop => triangle_subdivide::subdivide(mesh, linear_levels, levels, creases),
+        Scheme::CatmullClark => quad_subdivide::subdivide(mesh, linear_levels, levels, creases),
+    }
+}
+
+/// Scheme, linear levels, smoothing levels, crease threshold bits and
+/// colouring.
+type Key = (Scheme, u32, u32, Option<u32>, Coloring);
+
+/// The base mesh with the frame drawn for every level computed so far,
+/// keyed by scheme, crease setting and colouring.
+pub struct Cache {
+    pub base: Mesh,
+    /// Per-face textures, drawn on Catmull-Clark levels in place of the
+    /// colouring.
+    pub textures: Option<FaceTextures>,
+    triangles: triangle_subdivide::Levels,
+    quads: quad_subdivide::Levels,
+    frames: HashMap<Key, Frame>,
+}
+
+impl Cache {
+    pub fn new(base: Mesh, textures: Option<FaceTextures>) -> Cache {
+        Cache {
+            base,
+            textures,
+            triangles: Default::default(),
+            quads: Default::default(),
+            frames: HashMap::new(),
+        }
+    }
+
+    /// The frame to draw for the given levels, or `None` if `cancelled`
+    /// returned true first. Meshes with vertex colours, such as error maps
+    /// from `compare`, are drawn in those colours instead of the face
+    /// colouring, and textured Catmull-Clark levels in white under their
+    /// textures.
+    pub fn get_frame(
+        &mut self,
+        scheme: Scheme,
+        linear_levels: u32,
+        levels: u32,
+        creases: Option<f32>,
+        coloring: Coloring,
+        cancelled: &dyn Fn() -> bool,
+    ) -> Option<&Frame> {
+        let key = (
+            scheme,
+            linear_levels,
+            levels,
+            creases.map(f32::to_bits),
+            coloring,
+        );
+        if !self.frames.contains_key(&key) {
+            let base = &self.base;
+            let textures = self
+                .textures
+                .as_ref()
+                .filter(|_| scheme == Scheme::CatmullClark);
+            let frame = if let Some(textures) = textures {
+                let mesh = self
+                    .quads
+                    .get_mesh(base, linear_levels, levels, creases, cancelled)?;
+                mesh_frame(&textures.apply(base, &mesh))
+            } else if base.colors.is_some() {
+                let mesh = match scheme {
+                    Scheme::Loop => {
+                        self.triangles
+                            .get_mesh(base, linear_levels, levels, creases, cancelled)?
+                    }
+                    Scheme::CatmullClark => {
+                        self.quads
+                            .get_mesh(base, linear_levels, levels, creases, cancelled)?
+                    }
+                };
+                mesh_frame(&mesh)
+            } else {
+                match scheme {
+                    Scheme::Loop => self.triangles.get_frame(
+                        base,
+                        linear_levels,
+                        levels,
+                        creases,
+                        coloring,
+                        cancelled,
+                    )?,
+                    Scheme::CatmullClark => self.quads.get_frame(
+                        base,
+                        linear_levels,
+                        levels,
+                        creases,
+                        coloring,
+                        cancelled,
+                    )?,
+                }
+            };
+            self.frames.insert(key, frame);
+        }
+        Some(&self.frames[&key])
+    }
+
+    /// The level as `subdivide` would return it, reusing the steps the
+    /// frames have already run.
+    pub fn get_mesh(
+        &mut self,
+        scheme: Scheme,
+        linear_levels: u32,
+        levels: u32,
+        creases: Option<f32>,
+    ) -> Mesh {
+        let base = &self.base;
+        let never = &|| false;
+        match scheme {
+            Scheme::Loop => self
+                .triangles
+                .get_mesh(base, linear_levels, levels, creases, never),
+            Scheme::CatmullClark => {
+                self.quads
+                    .get_mesh(base, linear_levels, levels, creases, never)
+            }
+        }
+        .expect("the steps are never cancelled")
+    }
+}
+
+/// The frame of a mesh coloured and textured by its vertices, white where
+/// there are no colours.
+fn mesh_frame(mesh: &Mesh) -> Frame {
+    Frame::with_vertex_colors(
+        &mesh.positions,
+        &mesh.faces,
+        mesh.colors.as_deref(),
+        mesh.uvs.as_deref(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh;
+    use vecmath::*;
+
+    #[test]
+    fn cached_levels_match_subdivide() {
+        for (path, scheme) in [
+            ("assets/cube.obj", Scheme::Loop),
+            ("assets/quad_pentagonal_prism.obj", Scheme::CatmullClark),
+        ] {
+            let base = mesh::load(path).unwrap();
+            let mut triangles = triangle_subdivide::Levels::default();
+            let mut quads = quad_subdivide::Levels::default();
+            for (linear, levels, creases) in [(0, 2, None), (1, 1, Some(0.6))] {
+                let cached = match scheme {
+                    Scheme::Loop => triangles.get_mesh(&base, linear, levels, creases, &|| false),
+                    Scheme::CatmullClark => {
+                        quads.get_mesh(&base, linear, levels, creases, &|| false)
+                    }
+                }
+                .unwrap();
+                let direct = subdivide(scheme, &base, linear, levels, creases);
+                assert_eq!(cached.positions, direct.positions, "{}", path);
+                assert_eq!(cached.faces, direct.faces, "{}", path);
+            }
+        }
+    }
+
+    #[test]
+    fn cancelled_levels_give_up() {
+        let base = mesh::load("assets/quad_cube.obj").unwrap();
+        let mut quads = quad_subdivide::Levels::default();
+        assert!(quads.get_mesh(&base, 0, 2, None, &|| true).is_none());
+        assert!(quads.get_mesh(&base, 0, 0, None, &|| true).is_some());
+    }
+
+    /// Where `origin` puts each corner of its face in the base mesh.
+    fn corners_in_base(base: &Mesh, origin: &mesh::Origin) -> Vec<[f32; 3]> {
+        let face = &base.faces[origin.face];
+        origin
+            .corners
+            .iter()
+            .map(|weights| {
+                let mut p = [0.0; 3];
+                for (&v, &w) in face.iter().zip(weights) {
+                    p = vec3_add(p, vec3_scale(base.positions[v], w));
+                }
+                p
+            })
+            .collect()
+    }
+
+    /// Linear levels leave every vertex where its origin says it is.
+    fn assert_origins_place_corners(base: &Mesh, refined: &Mesh) {
+        for (face, origin) in refined.faces.iter().zip(&refined.origins) {
+            for (&v, p) in face.iter().zip(corners_in_base(base, origin)) {
+                let d = vec3_len(vec3_sub(refined.positions[v], p));
+                assert!(d < 1e-5, "{:?}: {:?} {:?}", origin, refined.positions[v], p);
+            }
+        }
+    }
+
+    #[test]
+    fn catmull_clark_faces_trace_back_to_the_base() {
+        let base = mesh::load("assets/quad_cube.obj").unwrap();
+        let refined = subdivide(Scheme::CatmullClark, &base, 0, 2, None);
+        assert_eq!(refined.origins.len(), 6 * 16);
+        // the children of each face follow it, four per level
+        for (f, origin) in refined.origins.iter().enumerate() {
+            assert_eq!(origin.face, f / 16);
+            assert_eq!(origin.piece, 0);
+            assert_eq!(origin.path, vec![(f / 4 % 4) as u8, (f % 4) as u8]);
+        }
+        assert_eq!(refined.origins[87].face, 5);
+        assert_eq!(refined.origins[87].path, vec![1, 3]);
+        assert_origins_place_corners(&base, &subdivide(Scheme::CatmullClark, &base, 2, 0, None));
+
+        // a pentagon is cut into one quad per corner, piece i at corner i
+        let base = mesh::load("assets/quad_pentagonal_prism.obj").unwrap();
+        let refined = subdivide(Scheme::CatmullClark, &base, 1, 0, None);
+        assert_origins_place_corners(&base, &refined);
+        for origin in &refined.origins {
+            let weights = &origin.corners[0];
+            if origin.path == [0] {
+                assert_eq!(weights[origin.piece], 1.0, "{:?}", origin);
+            }
+        }
+    }
+
+    #[test]
+    fn loop_pieces_follow_the_fan() {
+        let base = mesh::load("assets/quad_pentagonal_prism.obj").unwrap();
+        let refined = subdivide(Scheme::Loop, &base, 1, 0, None);
+        assert_origins_place_corners(&base, &refined);
+
+        // triangle i of the fan of face f spans its corners 0, i + 1 and i + 2
+        let fan: Vec<(usize, usize)> = base
+            .faces
+            .iter()
+            .enumerate()
+            .flat_map(|(f, face)| (0..face.len() - 2).map(move |i| (f, i)))
+            .collect();
+        let pieces: Vec<(usize, usize)> = refined
+            .origins
+            .chunks(4)
+            .map(|children| (children[0].face, children[0].piece))
+            .collect();
+        assert_eq!(pieces, fan);
+        let traced = triangle_subdivide::origins(&base, 0);
+        assert_eq!(traced.len(), fan.len());
+        for (origin, &(f, i)) in traced.iter().zip(&fan) {
+            assert_eq!((origin.face, origin.piece), (f, i));
+            for (corner, c) in origin.corners.iter().zip([0, i + 1, i + 2]) {
+                assert_eq!(corner[c], 1.0, "{:?}", origin);
+            }
+        }
+    }
+}
@@ -7,47 +7,104 @@ pub const VERTEX_SHADER: &str = "
                 in vec3 position;
                 in vec3 color;
                 in vec3 normal;
+                in vec2 tex_coords;
                 out vec3 v_position;
                 out vec3 v_color;
+                flat out vec3 v_face_color;
                 out vec3 v_normal;
+                out vec2 v_tex_coords;
 
                 void main() {
-                    v_position = position;
+                    // lighting is done in view space, where the eye is at the origin
+                    vec4 view_position = view_matrix * vec4(position, 1.0);
+                    v_position = view_position.xyz;
                     v_color = color;
-                    v_normal = normal;
-                    gl_Position = persp_matrix * view_matrix * vec4(v_position, 1.0);
+                    v_face_color = color;
+                    v_normal = mat3(view_matrix) * normal;
+                    v_tex_coords = tex_coords;
+                    gl_Position = persp_matrix * view_position;
                 }
             ";
 
-#[allow(dead_code)]
-pub const FRAGMENT_SHADER_FLAT: &str = "
+/// Colours each fragment by its vertex colour, or with `face_colors` by the
+/// colour of its triangle's provoking vertex, times the texture atlas while
+/// `textured` is set. `shading` 0 leaves the colour unlit, 1 draws grey
+/// Lambert and Phong lighting and 2 multiplies the colour by the lighting.
+/// With `faceted`, the lighting uses each triangle's own normal instead of
+/// the smooth vertex normals. The light sits just above and to the left of
+/// the eye, and faces are lit from whichever side is seen.
+pub const FRAGMENT_SHADER: &str = "
                 #version 140
 
+                uniform sampler2D tex;
+                uniform bool textured;
+                uniform int shading;
+                uniform bool faceted;
+                uniform bool face_colors;
+
+                in vec3 v_position;
                 in vec3 v_color;
+                flat in vec3 v_face_color;
+                in vec3 v_normal;
+                in vec2 v_tex_coords;
                 out vec4 f_color;
 
+                const vec3 light_offset = vec3(-0.3, 0.4, 0.0);
+                const float ambient = 0.15;
+                const float shininess = 32.0;
+
                 void main() {
-                    vec3 color = v_color;
-                    f_color = vec4(color, 1.0);
+                    vec3 color = face_colors ? v_face_color : v_color;
+                    if (textured) {
+                        color *= texture(tex, v_tex_coords).rgb;
+                    }
+                    if (shading == 0) {
+                        f_color = vec4(color, 1.0);
+                        return;
+                    }
+
+                    vec3 view_dir = normalize(-v_position);
+                    vec3 normal = faceted
+                        ? cross(dFdx(v_position), dFdy(v_position))
+                        : v_normal;
+                    normal = length(normal) > 0.0 ? normalize(normal) : view_dir;
+                    if (dot(normal, view_dir) < 0.0) {
+                        normal = -normal;
+                    }
+                    vec3 light_dir = normalize(view_dir + light_offset);
+                    vec3 half_dir = normalize(light_dir + view_dir);
+                    float diffuse = max(dot(normal, light_dir), 0.0);
+                    float specular = pow(max(dot(normal, half_dir), 0.0), shininess);
+
+                    vec3 base = shading == 1 ? vec3(0.8) : color;
+                    vec3 lit = base * (ambient + (1.0 - ambient) * diffuse) + vec3(0.3 * specular);
+                    f_color = vec4(min(lit, vec3(1.0)), 1.0);
                 }
             ";
 
-#[allow(dead_code)]
-pub const FRAGMENT_SHADER_SHADED: &str = "
+/// Draws the wireframe and control cage overlays in one colour, moved by
+/// `offset` into the frame of the mesh on screen.
+pub const LINE_VERTEX_SHADER: &str = "
                 #version 140
 
-                const vec3 light_position = vec3(10.0, 10.0, 10.0);
+                uniform mat4 persp_matrix;
+                uniform mat4 view_matrix;
+                uniform vec3 offset;
 
-                in vec3 v_color;
-                in vec3 v_normal;
-                in vec3 v_position;
+                in vec3 position;
+
+                void main() {
+                    gl_Position = persp_matrix * view_matrix * vec4(position - offset, 1.0);
+                }
+            ";
+
+pub const LINE_FRAGMENT_SHADER: &str = "
+                #version 140
+
+                uniform vec3 color;
                 out vec4 f_color;
 
                 void main() {
-                    vec3 light_dir = normalize(v_position - light_position);
-                    float lum = max(dot(light_dir, v_normal), 0.0);
-                    lum = max(lum, 0.2);
-                    vec3 color = v_color * lum;
                     f_color = vec4(color, 1.0);
                 }
             ";
@@ -0,0 +1,331 @@
+use crate::json;
+use crate::mesh::{face_normal, Mesh};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use vecmath::*;
+
+/// Topology and geometry numbers for one mesh.
+#[derive(Debug, Clone)]
+pub struct Stats {
+    pub vertices: usize,
+    pub edges: usize,
+    pub faces: usize,
+    pub euler_characteristic: i64,
+    pub components: usize,
+    pub boundary_loops: usize,
+    /// Only meaningful when every edge has at most two faces.
+    pub genus: i64,
+    /// Number of vertices with each valence (edges touching the vertex).
+    pub valences: BTreeMap<usize, usize>,
+    /// Vertices whose valence differs from the regular one for the mesh:
+    /// 6 inside and 4 on the boundary for triangles, 4 and 3 otherwise.
+    pub extraordinary: usize,
+    pub bounds: [[f32; 3]; 2],
+    pub area: f32,
+    /// Enclosed volume, only meaningful for closed, consistently oriented
+    /// meshes.
+    pub volume: f32,
+}
+
+fn find(parent: &mut [usize], x: usize) -> usize {
+    let mut x = x;
+    while parent[x] != x {
+        parent[x] = parent[parent[x]];
+        x = parent[x];
+    }
+    x
+}
+
+fn union(parent: &mut [usize], a: usize, b: usize) {
+    let a = find(parent, a);
+    let b = find(parent, b);
+    parent[a] = b;
+}
+
+/// Counts the boundary loops by walking the boundary half-edges. Where
+/// holes touch at a vertex, the walk leaves it by a boundary edge of
+/// another fan of faces than the one it came in by, so that each hole stays
+/// its own loop.
+fn boundary_loops(mesh: &Mesh, edges: &HashMap<[usize; 2], usize>) -> usize {
+    // half-edge -> (face, corner it leaves from)
+    let mut half_edges: HashMap<(usize, usize), (usize, usize)> = HashMap::new();
+    for (f, face) in mesh.faces.iter().enumerate() {
+        for i in 0..face.len() {
+            half_edges.insert((face[i], face[(i + 1) % face.len()]), (f, i));
+        }
+    }
+    let is_boundary = |a: usize, b: usize| edges[&[a.min(b), a.max(b)]] == 1;
+
+    let mut boundary: Vec<(usize, usize)> = half_edges
+        .keys()
+        .copied()
+        .filter(|&(a, b)| is_boundary(a, b))
+        .collect();
+    boundary.sort_unstable();
+    let mut leaving: HashMap<usize, Vec<(usize, usize)>> = HashMap::new();
+    for &(a, b) in &boundary {
+        leaving.entry(a).or_default().push((a, b));
+    }
+
+    // the boundary half-edge leaving v at the other end of the fan that
+    // (u, v) comes in by, found by turning through its faces
+    let same_fan = |(u, v): (usize, usize)| -> Option<(usize, usize)> {
+        let (mut f, mut i) = half_edges[&(u, v)];
+        for _ in 0..half_edges.len() {
+            let face = &mesh.faces[f];
+            let w = face[(i + 2) % face.len()];
+            if is_boundary(v, w) {
+                return Some((v, w));
+            }
+            (f, i) = *half_edges.get(&(w, v))?;
+        }
+        None
+    };
+
+    let mut visited = HashSet::new();
+    let mut loops = 0;
+    for &start in &boundary {
+        if !visited.insert(start) {
+            continue;
+        }
+        let mut h = start;
+        loop {
+            let same = same_fan(h);
+            let other = leaving
+                .get(&h.1)
+                .into_iter()
+                .flatten()
+                .copied()
+                .find(|&n| Some(n) != same && !visited.contains(&n));
+            match other.or(same) {
+                Some(n) if visited.insert(n) => h = n,
+                _ => break,
+            }
+        }
+        loops += 1;
+    }
+    loops
+}
+
+impl Stats {
+    pub fn compute(mesh: &Mesh) -> Stats {
+        let mut edges: HashMap<[usize; 2], usize> = HashMap::new();
+        for face in &mesh.faces {
+            for i in 0..face.len() {
+                let a = face[i];
+                let b = face[(i + 1) % face.len()];
+                *edges.entry([a.min(b), a.max(b)]).or_insert(0) += 1;
+            }
+        }
+
+        let mut valence = vec![0; mesh.positions.len()];
+        let mut on_boundary = vec![false; mesh.positions.len()];
+        let mut components = (0..mesh.positions.len()).collect::<Vec<_>>();
+        for (&[a, b], &count) in &edges {
+            valence[a] += 1;
+            valence[b] += 1;
+            union(&mut components, a, b);
+            if count == 1 {
+                on_boundary[a] = true;
+                on_boundary[b] = true;
+            }
+        }
+
+        let used: Vec<usize> = (0..mesh.positions.len())
+            .filter(|&v| valence[v] > 0)
+            .collect();
+        let mut component_roots: Vec<usize> =
+            used.iter().map(|&v| find(&mut components, v)).collect();
+        component_roots.sort_unstable();
+        component_roots.dedup();
+        let boundary_loops = boundary_loops(mesh, &edges);
+
+        let regular = if mesh.is_triangles() { (6, 4) } else { (4, 3) };
+        let mut valences = BTreeMap::new();
+        let mut extraordinary = 0;
+        for &v in &used {
+            *valences.entry(valence[v]).or_insert(0) += 1;
+            let expected = if on_boundary[v] { regular.1 } else { regular.0 };
+            if valence[v] != expected {
+                extraordinary += 1;
+            }
+        }
+
+        let mut bounds = [[f32::INFINITY; 3], [f32::NEG_INFINITY; 3]];
+        for &v in &used {
+            for (k, &x) in mesh.positions[v].iter().enumerate() {
+                bounds[0][k] = bounds[0][k].min(x);
+                bounds[1][k] = bounds[1][k].max(x);
+            }
+        }
+        if used.is_empty() {
+            bounds = [[0.0; 3]; 2];
+        }
+
+        // the volume is measured from the middle of the box, not the origin,
+        // so that it keeps its precision for meshes placed far from it
+        let middle = vec3_scale(vec3_add(bounds[0], bounds[1]), 0.5);
+        let at = |v: usize| vec3_sub(mesh.positions[v], middle);
+        let mut area = 0.0;
+        let mut volume = 0.0;
+        for face in mesh.faces.iter().filter(|f| f.len() >= 3) {
+            area += vec3_len(face_normal(&mesh.positions, face)) / 2.0;
+            let a = at(face[0]);
+            for i in 1..face.len() - 1 {
+                let b = at(face[i]);
+                let c = at(face[i + 1]);
+                volume += vec3_dot(a, vec3_cross(b, c)) / 6.0;
+            }
+        }
+
+        let euler_characteristic = used.len() as i64 - edges.len() as i64 + mesh.faces.len() as i64;
+        let genus =
+            (2 * component_roots.len() as i64 - boundary_loops as i64 - euler_characteristic) / 2;
+
+        Stats {
+            vertices: used.len(),
+            edges: edges.len(),
+            faces: mesh.faces.len(),
+            euler_characteristic,
+            components: component_roots.len(),
+            boundary_loops,
+            genus,
+            valences,
+            extraordinary,
+            bounds,
+            area,
+            volume: volume.abs(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let valences: Vec<String> = self
+            .valences
+            .iter()
+            .map(|(valence, count)| format!("{}:{}", json::string(&valence.to_string()), count))
+            .collect();
+        let bounds = self.bounds.map(|b| json::array(b.map(json::number)));
+        format!(
+            concat!(
+                r#"{{"vertices":{},"edges":{},"faces":{},"euler_characteristic":{},"#,
+                r#""components":{},"boundary_loops":{},"genus":{},"valences":{{{}}},"#,
+                r#""extraordinary_vertices":{},"bounds":{{"min":{},"max":{}}},"#,
+                r#""area":{},"volume":{}}}"#
+            ),
+            self.vertices,
+            self.edges,
+            self.faces,
+            self.euler_characteristic,
+            self.components,
+            self.boundary_loops,
+            self.genus,
+            valences.join(","),
+            self.extraordinary,
+            bounds[0],
+            bounds[1],
+            json::number(self.area),
+            json::number(self.volume)
+        )
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "vertices:               {}", self.vertices)?;
+        writeln!(f, "edges:                  {}", self.edges)?;
+        writeln!(f, "faces:                  {}", self.faces)?;
+        writeln!(f, "euler characteristic:   {}", self.euler_characteristic)?;
+        writeln!(f, "components:             {}", self.components)?;
+        writeln!(f, "boundary loops:         {}", self.boundary_loops)?;
+        writeln!(f, "genus:                  {}", self.genus)?;
+        writeln!(f, "extraordinary vertices: {}", self.extraordinary)?;
+        writeln!(f, "valences:")?;
+        for (valence, count) in &self.valences {
+            writeln!(f, "  {:>3}: {}", valence, count)?;
+        }
+        writeln!(
+            f,
+            "bounding box:           {:?} to {:?}",
+            self.bounds[0], self.bounds[1]
+        )?;
+        writeln!(f, "surface area:           {}", self.area)?;
+        write!(f, "volume:                 {}", self.volume)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh;
+
+    /// An `n` by `m` grid of quads, with those `keep` rejects left out, or
+    /// closed up into a torus.
+    fn grid(n: usize, m: usize, torus: bool, keep: impl Fn(usize, usize) -> bool) -> Mesh {
+        let (columns, rows) = if torus { (n, m) } else { (n + 1, m + 1) };
+        let mut positions = Vec::new();
+        for j in 0..rows {
+            for i in 0..columns {
+                positions.push(if torus {
+                    let (a, b) = (
+                        std::f32::consts::TAU * i as f32 / n as f32,
+                        std::f32::consts::TAU * j as f32 / m as f32,
+                    );
+                    let r = 2.0 + b.cos();
+                    [r * a.cos(), r * a.sin(), b.sin()]
+                } else {
+                    [i as f32, j as f32, 0.0]
+                });
+            }
+        }
+        let at = |i: usize, j: usize| (j % rows) * columns + i % columns;
+        let mut faces = Vec::new();
+        for j in 0..m {
+            for i in 0..n {
+                if keep(i, j) {
+                    faces.push(vec![at(i, j), at(i + 1, j), at(i + 1, j + 1), at(i, j + 1)]);
+                }
+            }
+        }
+        Mesh {
+            positions,
+            faces,
+            ..Mesh::default()
+        }
+    }
+
+    #[test]
+    fn closed_cube() {
+        let stats = Stats::compute(&mesh::load("assets/quad_cube.obj").unwrap());
+        assert_eq!(stats.euler_characteristic, 2);
+        assert_eq!(stats.genus, 0);
+        assert_eq!(stats.boundary_loops, 0);
+        assert!((stats.volume - 8.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn open_grid() {
+        let stats = Stats::compute(&grid(4, 3, false, |_, _| true));
+        assert_eq!(stats.euler_characteristic, 1);
+        assert_eq!(stats.genus, 0);
+        assert_eq!(stats.boundary_loops, 1);
+        assert_eq!(stats.area, 12.0);
+    }
+
+    #[test]
+    fn holes_touching_at_a_vertex() {
+        let stats = Stats::compute(&grid(4, 4, false, |i, j| {
+            (i, j) != (1, 1) && (i, j) != (2, 2)
+        }));
+        assert_eq!(stats.boundary_loops, 3);
+        assert_eq!(stats.genus, 0);
+    }
+
+    #[test]
+    fn torus() {
+        let stats = Stats::compute(&grid(8, 6, true, |_, _| true));
+        assert_eq!(stats.euler_characteristic, 0);
+        assert_eq!(stats.genus, 1);
+        assert_eq!(stats.boundary_loops, 0);
+    }
+}
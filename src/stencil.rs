@@ -0,0 +1,601 @@
+use crate::mesh::{self, Creases, Mesh, Origin};
+use crate::quad_subdivide;
+use crate::scheme::Scheme;
+use crate::triangle_subdivide;
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufWriter};
+use vecmath::*;
+
+/// A refined vertex as a weighted sum of control vertices, sorted by index.
+/// The weights are kept in f64 so that composing many levels of them does
+/// not lose the precision the matrix is written with.
+pub type Stencil = Vec<(usize, f64)>;
+
+/// A subdivision recorded as one `Stencil` per refined vertex, so that a mesh
+/// whose control points move but whose topology stays fixed can be refined
+/// again with a sparse multiply instead of a full subdivision.
+///
+/// The rules are those of the Loop and Catmull-Clark dividers, worked on
+/// vertex indices. Crease decisions depend on positions, so they are made
+/// once on the rest pose and kept for every pose after. Where an edge has
+/// more than two faces both take the first other face in face order.
+pub struct StencilTable {
+    pub control_vertices: usize,
+    pub stencils: Vec<Stencil>,
+    pub faces: Vec<Vec<usize>>,
+    origins: Vec<Origin>,
+}
+
+/// Which edges and vertices a smoothing step keeps sharp.
+pub enum Sharp {
+    /// Those whose faces' normals have a dot product below the threshold,
+    /// tested the way the dividers test them. `None` keeps nothing sharp.
+    Angle(Option<f32>),
+    /// The given edges, lowest vertex first, and corners, whatever the
+    /// positions.
+    Marked {
+        edges: HashSet<[usize; 2]>,
+        corners: HashSet<usize>,
+    },
+}
+
+impl Sharp {
+    /// The edges and corners of `creases`.
+    pub fn marked(creases: &Creases) -> Sharp {
+        Sharp::Marked {
+            edges: creases.edges.iter().copied().collect(),
+            corners: creases.corners.iter().copied().collect(),
+        }
+    }
+
+    fn edge(&self, a: usize, b: usize, dot: impl FnOnce() -> f32) -> bool {
+        match self {
+            Sharp::Angle(creases) => creases.is_some_and(|c| dot() < c),
+            Sharp::Marked { edges, .. } => edges.contains(&[a.min(b), a.max(b)]),
+        }
+    }
+
+    fn corner(&self, v: usize, dot: impl FnOnce() -> f32) -> bool {
+        match self {
+            Sharp::Angle(creases) => creases.is_some_and(|c| dot() < c),
+            Sharp::Marked { corners, .. } => corners.contains(&v),
+        }
+    }
+}
+
+/// One level of the refinement: positions for the crease tests, and the
+/// stencils of each vertex over the control vertices.
+struct Level {
+    positions: Vec<[f32; 3]>,
+    faces: Vec<Vec<usize>>,
+    stencils: Vec<Stencil>,
+}
+
+impl StencilTable {
+    /// Refines `base` the way `scheme::subdivide` does.
+    pub fn new(
+        scheme: Scheme,
+        base: &Mesh,
+        linear_levels: u32,
+        levels: u32,
+        creases: Option<f32>,
+    ) -> StencilTable {
+        let control = Level {
+            positions: base.positions.clone(),
+            faces: base.faces.clone(),
+            stencils: identity(base.positions.len()),
+        };
+        let (control, mut apart) = weld(&control, &base.kept_apart(1e-4));
+        let (cage, origins) = match scheme {
+            Scheme::Loop => (
+                triangle_cage(&control),
+                triangle_subdivide::origins(base, linear_levels + levels),
+            ),
+            Scheme::CatmullClark => (
+                quad_cage(&control),
+                quad_subdivide::origins(base, linear_levels + levels),
+            ),
+        };
+        apart.resize(cage.positions.len(), None);
+        let (mut level, mut apart) = weld(&cage, &apart);
+
+        for step_index in 0..linear_levels + levels {
+            let smooth = step_index >= linear_levels;
+            let (rules, faces) = step(
+                scheme,
+                &level.positions,
+                &level.faces,
+                smooth.then_some(&Sharp::Angle(creases)),
+            );
+            let next = Level {
+                positions: rules.iter().map(|r| apply(r, &level.positions)).collect(),
+                stencils: rules.iter().map(|r| compose(r, &level.stencils)).collect(),
+                faces,
+            };
+            // the rules keep the old vertices first, so vertex points keep
+            // their keys
+            apart.resize(next.positions.len(), None);
+            (level, apart) = weld(&next, &apart);
+        }
+
+        StencilTable {
+            control_vertices: base.positions.len(),
+            stencils: level.stencils,
+            faces: level.faces,
+            origins,
+        }
+    }
+
+    /// The refined positions for new control point positions.
+    pub fn apply(&self, control: &[[f32; 3]]) -> Vec<[f32; 3]> {
+        self.stencils.iter().map(|s| apply(s, control)).collect()
+    }
+
+    /// The refined mesh for `base` with its control points moved to
+    /// `control`, carrying its attributes like `scheme::subdivide`.
+    pub fn refine(&self, base: &Mesh, control: &[[f32; 3]]) -> Mesh {
+        Mesh::refined(base, self.apply(control), self.faces.clone(), &self.origins)
+    }
+
+    /// Writes the stencils as a Matrix Market coordinate matrix with one row
+    /// per refined vertex and one column per control vertex, both counted
+    /// from 1.
+    pub fn write_matrix_market(&self, path: &str, comment: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let entries: usize = self.stencils.iter().map(Vec::len).sum();
+
+        writeln!(out, "%%MatrixMarket matrix coordinate real general")?;
+        for line in comment.lines() {
+            writeln!(out, "% {}", line)?;
+        }
+        writeln!(
+            out,
+            "{} {} {}",
+            self.stencils.len(),
+            self.control_vertices,
+            entries
+        )?;
+        for (row, stencil) in self.stencils.iter().enumerate() {
+            for &(column, weight) in stencil {
+                writeln!(out, "{} {} {}", row + 1, column + 1, weight)?;
+            }
+        }
+        out.flush()
+    }
+
+    /// Writes the refined faces one per line, as matrix rows counted from 1.
+    pub fn write_faces(&self, path: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        for face in &self.faces {
+            let face: Vec<String> = face.iter().map(|v| (v + 1).to_string()).collect();
+            writeln!(out, "{}", face.join(" "))?;
+        }
+        out.flush()
+    }
+}
+
+fn apply(stencil: &[(usize, f64)], positions: &[[f32; 3]]) -> [f32; 3] {
+    let p = stencil.iter().fold([0.0, 0.0, 0.0], |p, &(i, w)| {
+        vec3_add(p, vec3_scale(positions[i].map(f64::from), w))
+    });
+    p.map(|x| x as f32)
+}
+
+/// One linear step, or with `sharp` set one smoothing step, of triangles for
+/// Loop or quads for Catmull-Clark. Returns a rule over the old vertices for
+/// each new vertex, the old vertices first and in their old order, and the new
+/// faces.
+pub fn step(
+    scheme: Scheme,
+    positions: &[[f32; 3]],
+    faces: &[Vec<usize>],
+    sharp: Option<&Sharp>,
+) -> (Vec<Stencil>, Vec<Vec<usize>>) {
+    let level = Level {
+        positions: positions.to_vec(),
+        faces: faces.to_vec(),
+        stencils: Vec::new(),
+    };
+    match scheme {
+        Scheme::Loop => loop_step(&level, sharp),
+        Scheme::CatmullClark => catmull_step(&level, sharp),
+    }
+}
+
+/// Substitutes the previous level's stencils into a rule over its vertices.
+fn compose(rule: &[(usize, f64)], stencils: &[Stencil]) -> Stencil {
+    merge(
+        rule.iter()
+            .flat_map(|&(i, w)| stencils[i].iter().map(move |&(j, v)| (j, w * v)))
+            .collect(),
+    )
+}
+
+/// Sorts the weights by index, adding up those of the same vertex.
+fn merge(mut r: Stencil) -> Stencil {
+    r.sort_by_key(|&(j, _)| j);
+    r.dedup_by(|next, kept| {
+        if next.0 == kept.0 {
+            kept.1 += next.1;
+            true
+        } else {
+            false
+        }
+    });
+    r
+}
+
+fn identity(n: usize) -> Vec<Stencil> {
+    (0..n).map(|i| vec![(i, 1.0)]).collect()
+}
+
+/// Welds the vertices of the level the way the dividers weld their corners
+/// before every step and at the end: by position, except where the `apart`
+/// keys differ, numbered in the order the faces first use them. Returns the
+/// keys of the welded vertices.
+fn weld(level: &Level, apart: &[Option<usize>]) -> (Level, Vec<Option<usize>>) {
+    let corners = level.faces.concat();
+    let positions: Vec<[f32; 3]> = corners.iter().map(|&v| level.positions[v]).collect();
+    let keys: Vec<Option<usize>> = corners.iter().map(|&v| apart[v]).collect();
+    let (remap, kept) = mesh::weld_apart(&positions, &keys, 1e-4);
+
+    let mut start = 0;
+    let faces = level
+        .faces
+        .iter()
+        .map(|face| {
+            start += face.len();
+            remap[start - face.len()..start].to_vec()
+        })
+        .collect();
+    let kept: Vec<usize> = kept.iter().map(|&c| corners[c]).collect();
+    let welded = Level {
+        positions: kept.iter().map(|&v| level.positions[v]).collect(),
+        faces,
+        stencils: kept.iter().map(|&v| level.stencils[v].clone()).collect(),
+    };
+    (welded, kept.iter().map(|&v| apart[v]).collect())
+}
+
+/// The fan triangulation made by the Loop loader.
+fn triangle_cage(control: &Level) -> Level {
+    let mut faces = Vec::new();
+    for face in &control.faces {
+        for i in 1..face.len() - 1 {
+            faces.push(vec![face[0], face[i], face[i + 1]]);
+        }
+    }
+    Level {
+        positions: control.positions.clone(),
+        faces,
+        stencils: control.stencils.clone(),
+    }
+}
+
+/// The quads made by the Catmull-Clark loader, with a centroid and edge
+/// midpoints added for every face that is not a quad.
+fn quad_cage(control: &Level) -> Level {
+    let mut rules = identity(control.positions.len());
+    let mut midpoints = HashMap::new();
+    let mut faces = Vec::new();
+    for face in &control.faces {
+        let n = face.len();
+        if n == 4 {
+            faces.push(face.clone());
+            continue;
+        }
+
+        rules.push(merge(face.iter().map(|&v| (v, 1.0 / n as f64)).collect()));
+        let center = rules.len() - 1;
+        let mut midpoint = |a: usize, b: usize| {
+            *midpoints.entry([a.min(b), a.max(b)]).or_insert_with(|| {
+                rules.push(merge(vec![(a, 0.5), (b, 0.5)]));
+                rules.len() - 1
+            })
+        };
+        for i in 0..n {
+            let v = face[i];
+            let next = midpoint(v, face[(i + 1) % n]);
+            let prev = midpoint(v, face[(i + n - 1) % n]);
+            faces.push(vec![v, next, center, prev]);
+        }
+    }
+
+    Level {
+        positions: rules.iter().map(|r| apply(r, &control.positions)).collect(),
+        faces,
+        stencils: rules
+            .iter()
+            .map(|r| compose(r, &control.stencils))
+            .collect(),
+    }
+}
+
+/// Faces using each vertex, in face order.
+fn vertex_faces(level: &Level) -> Vec<Vec<usize>> {
+    let mut r = vec![Vec::new(); level.positions.len()];
+    for (f, face) in level.faces.iter().enumerate() {
+        for &v in face {
+            if !r[v].contains(&f) {
+                r[v].push(f);
+            }
+        }
+    }
+    r
+}
+
+/// Smallest dot product between the normals of any two of `faces`, 1 if there
+/// are fewer than two.
+fn min_dot(normals: &[[f32; 3]], faces: &[usize]) -> f32 {
+    let mut s = 1.0;
+    for &i in faces {
+        for &j in faces {
+            if i != j {
+                s = vec3_dot(normals[i], normals[j]).min(s);
+            }
+        }
+    }
+    s
+}
+
+/// The normal the dividers use for crease tests, from the first three corners.
+fn face_normal(positions: &[[f32; 3]], face: &[usize]) -> [f32; 3] {
+    let e1 = vec3_normalized(vec3_sub(positions[face[0]], positions[face[1]]));
+    let e2 = vec3_normalized(vec3_sub(positions[face[1]], positions[face[2]]));
+    vec3_normalized(vec3_cross(e1, e2))
+}
+
+/// Another face using both `a` and `b`.
+fn other_face(vertex_faces: &[Vec<usize>], a: usize, b: usize, me: usize) -> Option<usize> {
+    vertex_faces[a]
+        .iter()
+        .copied()
+        .find(|&f| f != me && vertex_faces[b].contains(&f))
+}
+
+fn loop_step(level: &Level, sharp: Option<&Sharp>) -> (Vec<Stencil>, Vec<Vec<usize>>) {
+    let n = level.positions.len();
+    let p = &level.positions;
+    let vertex_faces = vertex_faces(level);
+    let normals: Vec<[f32; 3]> = level.faces.iter().map(|f| face_normal(p, f)).collect();
+
+    let mut rules: Vec<Stencil> = (0..n)
+        .map(|v| match sharp {
+            None => vec![(v, 1.0)],
+            Some(sharp) => loop_even(level, &vertex_faces, &normals, v, sharp),
+        })
+        .collect();
+
+    let mut edges = HashMap::new();
+    let mut odd = |a: usize, b: usize, c: usize, f: usize| {
+        *edges.entry([a.min(b), a.max(b)]).or_insert_with(|| {
+            let d = other_face(&vertex_faces, a, b, f).map(|g| {
+                let g = &level.faces[g];
+                *g[..2].iter().find(|&&x| x != a && x != b).unwrap_or(&g[2])
+            });
+            let rule = match (sharp, d) {
+                (Some(sharp), Some(d)) => {
+                    let dot = || {
+                        let shared_edge = vec3_normalized(vec3_sub(p[a], p[b]));
+                        let e1 = vec3_normalized(vec3_sub(p[a], p[c]));
+                        let e2 = vec3_normalized(vec3_sub(p[a], p[d]));
+                        let n1 = vec3_normalized(vec3_cross(shared_edge, e1));
+                        let n2 = vec3_normalized(vec3_cross(e2, shared_edge));
+                        vec3_dot(n1, n2)
+                    };
+                    if sharp.edge(a, b, dot) {
+                        vec![(a, 0.5), (b, 0.5)]
+                    } else {
+                        vec![
+                            (a, 3.0 / 8.0),
+                            (b, 3.0 / 8.0),
+                            (c, 1.0 / 8.0),
+                            (d, 1.0 / 8.0),
+                        ]
+                    }
+                }
+                _ => vec![(a, 0.5), (b, 0.5)],
+            };
+            rules.push(rule);
+            rules.len() - 1
+        })
+    };
+
+    let mut faces = Vec::new();
+    for (f, face) in level.faces.iter().enumerate() {
+        let [a, b, c] = [face[0], face[1], face[2]];
+        let ab = odd(a, b, c, f);
+        let bc = odd(b, c, a, f);
+        let ac = odd(c, a, b, f);
+        faces.push(vec![a, ab, ac]);
+        faces.push(vec![b, bc, ab]);
+        faces.push(vec![c, ac, bc]);
+        faces.push(vec![ab, bc, ac]);
+    }
+    (rules, faces)
+}
+
+fn loop_even(
+    level: &Level,
+    vertex_faces: &[Vec<usize>],
+    normals: &[[f32; 3]],
+    v: usize,
+    sharp: &Sharp,
+) -> Stencil {
+    let mut surrounding = Vec::new();
+    for &f in &vertex_faces[v] {
+        for &u in &level.faces[f] {
+            if u != v && !surrounding.contains(&u) {
+                surrounding.push(u);
+            }
+        }
+    }
+
+    let n = surrounding.len();
+    if n == 2 {
+        let mut r = vec![(v, 3.0 / 4.0)];
+        r.extend(surrounding.iter().map(|&u| (u, 1.0 / 8.0)));
+        return r;
+    }
+
+    if sharp.corner(v, || min_dot(normals, &vertex_faces[v])) {
+        return vec![(v, 1.0)];
+    }
+
+    let k = n as f64;
+    let beta = if n == 3 { 3.0 / 16.0 } else { 3.0 / (8.0 * k) };
+    let mut r = vec![(v, 1.0 - k * beta)];
+    r.extend(surrounding.iter().map(|&u| (u, beta)));
+    r
+}
+
+fn catmull_step(level: &Level, sharp: Option<&Sharp>) -> (Vec<Stencil>, Vec<Vec<usize>>) {
+    let n = level.positions.len();
+    let p = &level.positions;
+    let vertex_faces = vertex_faces(level);
+    let normals: Vec<[f32; 3]> = level.faces.iter().map(|f| face_normal(p, f)).collect();
+    let center = |f: usize| -> Stencil { level.faces[f].iter().map(|&v| (v, 0.25)).collect() };
+
+    let mut rules: Vec<Stencil> = (0..n)
+        .map(|v| match sharp {
+            None => vec![(v, 1.0)],
+            Some(sharp) => catmull_even(level, &vertex_faces, &normals, v, sharp),
+        })
+        .collect();
+
+    let mut edges = HashMap::new();
+    let mut edge = |a: usize, b: usize, f: usize, rules: &mut Vec<Stencil>| {
+        *edges.entry([a.min(b), a.max(b)]).or_insert_with(|| {
+            let midpoint = vec![(a, 0.5), (b, 0.5)];
+            let rule = match (sharp, other_face(&vertex_faces, a, b, f)) {
+                (Some(sharp), Some(g)) => {
+                    if sharp.edge(a, b, || vec3_dot(normals[f], normals[g])) {
+                        midpoint
+                    } else {
+                        let mut r = vec![(a, 0.25), (b, 0.25)];
+                        r.extend(center(f).iter().map(|&(v, w)| (v, w / 4.0)));
+                        r.extend(center(g).iter().map(|&(v, w)| (v, w / 4.0)));
+                        merge(r)
+                    }
+                }
+                _ => midpoint,
+            };
+            rules.push(rule);
+            rules.len() - 1
+        })
+    };
+
+    let mut faces = Vec::new();
+    for (f, face) in level.faces.iter().enumerate() {
+        let [a, b, c, d] = [face[0], face[1], face[2], face[3]];
+        rules.push(center(f));
+        let center = rules.len() - 1;
+        let e1 = edge(a, b, f, &mut rules);
+        let e2 = edge(b, c, f, &mut rules);
+        let e3 = edge(c, d, f, &mut rules);
+        let e4 = edge(a, d, f, &mut rules);
+        faces.push(vec![a, e1, center, e4]);
+        faces.push(vec![e1, b, e2, center]);
+        faces.push(vec![center, e2, c, e3]);
+        faces.push(vec![e4, center, e3, d]);
+    }
+    (rules, faces)
+}
+
+fn catmull_even(
+    level: &Level,
+    vertex_faces: &[Vec<usize>],
+    normals: &[[f32; 3]],
+    v: usize,
+    sharp: &Sharp,
+) -> Stencil {
+    let faces = &vertex_faces[v];
+    if faces.is_empty() || sharp.corner(v, || min_dot(normals, faces)) {
+        return vec![(v, 1.0)];
+    }
+
+    // (F + 2R + (n - 3) v) / n, with F the average face point and R the
+    // average of the edge midpoints seen from each face
+    let n = faces.len() as f64;
+    let mut r = vec![(v, (n - 3.0) / n)];
+    for &f in faces {
+        let face = &level.faces[f];
+        let i = face.iter().position(|&u| u == v).unwrap();
+        for &u in face {
+            r.push((u, 0.25 / (n * n)));
+        }
+        for u in [face[(i + 3) % 4], face[(i + 1) % 4]] {
+            r.push((v, 0.5 / (n * n)));
+            r.push((u, 0.5 / (n * n)));
+        }
+    }
+    merge(r)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repair::{self, RepairOptions};
+    use crate::scheme;
+
+    /// Checks the stencils against the dividers, which weld their corners by
+    /// position at every level.
+    fn assert_matches_subdivide(rest: &Mesh, scheme: Scheme, linear: u32, levels: u32) {
+        for creases in [None, Some(mesh::CREASE_COSINE)] {
+            let table = StencilTable::new(scheme, rest, linear, levels, creases);
+            let refined = table.refine(rest, &rest.positions);
+            let direct = scheme::subdivide(scheme, rest, linear, levels, creases);
+            let at = format!("{:?} {}+{} {:?}", scheme, linear, levels, creases);
+            assert_eq!(refined.faces, direct.faces, "{}", at);
+            for (a, b) in refined.positions.iter().zip(&direct.positions) {
+                let d = vec3_len(vec3_sub(*a, *b));
+                assert!(d < 1e-5, "{}: {:?} {:?}", at, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn rest_pose_matches_subdivide() {
+        for (path, scheme) in [
+            ("assets/cube.obj", Scheme::Loop),
+            ("assets/triangular_pyramid.obj", Scheme::Loop),
+            ("assets/half_sphere.obj", Scheme::Loop),
+            ("assets/quad_cube.obj", Scheme::CatmullClark),
+            ("assets/quad_pentagonal_prism.obj", Scheme::CatmullClark),
+            ("assets/quad_half_sphere.obj", Scheme::CatmullClark),
+        ] {
+            let rest = mesh::load(path).unwrap();
+            for (linear, levels) in [(0, 1), (0, 2), (1, 2)] {
+                assert_matches_subdivide(&rest, scheme, linear, levels);
+            }
+        }
+    }
+
+    #[test]
+    fn split_vertices_match_subdivide() {
+        // two cubes touching at a corner, split into a copy for each
+        let cube = mesh::load("assets/quad_cube.obj").unwrap();
+        let corner = cube.positions[0];
+        let mut rest = cube.clone();
+        for p in &cube.positions {
+            rest.positions.push(vec3_sub(vec3_scale(corner, 2.0), *p));
+        }
+        let n = cube.positions.len();
+        for face in &cube.faces {
+            rest.faces.push(face.iter().map(|&v| v + n).collect());
+        }
+        rest.face_groups.clear();
+        rest.weld(1e-4);
+        let options = RepairOptions {
+            weld: 1e-4,
+            fill_holes: None,
+        };
+        assert_eq!(repair::repair(&mut rest, &options).split_vertices, 1);
+        for scheme in [Scheme::Loop, Scheme::CatmullClark] {
+            assert_matches_subdivide(&rest, scheme, 0, 2);
+        }
+    }
+}
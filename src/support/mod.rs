@@ -18,10 +18,10 @@ where
     let mut next_frame_time = Instant::now();
     event_loop.run(move |event, _, control_flow| {
         let run_callback = match event.to_static() {
-            Some(Event::NewEvents(cause)) => match cause {
-                StartCause::ResumeTimeReached { .. } | StartCause::Init => true,
-                _ => false,
-            },
+            Some(Event::NewEvents(cause)) => matches!(
+                cause,
+                StartCause::ResumeTimeReached { .. } | StartCause::Init
+            ),
             Some(event) => {
                 events_buffer.push(event);
                 false
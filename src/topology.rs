@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+
+/// The neighbourhoods of an indexed level that the smoothing rules read, so
+/// that each face, edge and vertex point can be worked out once.
+pub struct Topology {
+    /// The faces using each vertex, in face order.
+    pub vertex_faces: Vec<Vec<usize>>,
+    /// Every edge once, its ends in the order the first face using it runs.
+    pub edges: Vec<[usize; 2]>,
+    /// The faces using each edge, in face order.
+    pub edge_faces: Vec<Vec<usize>>,
+    /// For every face, the edge from each corner to the next.
+    pub face_edges: Vec<Vec<usize>>,
+}
+
+impl Topology {
+    pub fn new(vertices: usize, faces: &[Vec<usize>]) -> Topology {
+        let mut vertex_faces = vec![Vec::new(); vertices];
+        let mut index: HashMap<[usize; 2], usize> = HashMap::new();
+        let mut edges = Vec::new();
+        let mut edge_faces: Vec<Vec<usize>> = Vec::new();
+        let mut face_edges = Vec::with_capacity(faces.len());
+
+        for (f, face) in faces.iter().enumerate() {
+            let mut corners = Vec::with_capacity(face.len());
+            for (i, &a) in face.iter().enumerate() {
+                if vertex_faces[a].last() != Some(&f) {
+                    vertex_faces[a].push(f);
+                }
+                let b = face[(i + 1) % face.len()];
+                let e = *index.entry([a.min(b), a.max(b)]).or_insert_with(|| {
+                    edges.push([a, b]);
+                    edge_faces.push(Vec::new());
+                    edges.len() - 1
+                });
+                if edge_faces[e].last() != Some(&f) {
+                    edge_faces[e].push(f);
+                }
+                corners.push(e);
+            }
+            face_edges.push(corners);
+        }
+
+        Topology {
+            vertex_faces,
+            edges,
+            edge_faces,
+            face_edges,
+        }
+    }
+
+    /// A face other than `f` across edge `e`, the first one if there are
+    /// several.
+    pub fn across(&self, e: usize, f: usize) -> Option<usize> {
+        self.edge_faces[e].iter().copied().find(|&g| g != f)
+    }
+}
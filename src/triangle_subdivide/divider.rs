@@ -1,3 +1,6 @@
+use crate::mesh::Origin;
+use crate::parallel;
+use crate::topology::Topology;
 use crate::triangle_subdivide::*;
 use vecmath::*;
 
@@ -11,6 +14,7 @@ fn average_n(a: &Vertex, b: &Vertex, x: f32, y: f32) -> Vertex {
             vec3_scale(a.position, x / (x + y)),
             vec3_scale(b.position, y / (x + y)),
         ),
+        base: None,
     }
 }
 
@@ -36,7 +40,7 @@ pub fn linear_subdivide_once(triangles: Vec<Triangle>) -> Vec<Triangle> {
             vertices: [a, ab, ac],
         });
         tris.push(Triangle {
-            vertices: [b, ab, bc],
+            vertices: [b, bc, ab],
         });
         tris.push(Triangle {
             vertices: [c, ac, bc],
@@ -49,7 +53,30 @@ pub fn linear_subdivide_once(triangles: Vec<Triangle>) -> Vec<Triangle> {
     tris
 }
 
-pub fn loop_subdivide(triangles: Vec<Triangle>, levels: u32, creases: bool) -> Vec<Triangle> {
+/// Corner weights of the four children made by `linear_subdivide_once` and
+/// `loop_subdivide_once`, in the order they are pushed.
+const CHILDREN: [[[f32; 3]; 3]; 4] = [
+    [[1.0, 0.0, 0.0], [0.5, 0.5, 0.0], [0.5, 0.0, 0.5]],
+    [[0.0, 1.0, 0.0], [0.0, 0.5, 0.5], [0.5, 0.5, 0.0]],
+    [[0.0, 0.0, 1.0], [0.5, 0.0, 0.5], [0.0, 0.5, 0.5]],
+    [[0.5, 0.5, 0.0], [0.0, 0.5, 0.5], [0.5, 0.0, 0.5]],
+];
+
+/// Follows the triangles' origins through `levels` subdivision steps.
+pub fn subdivide_origins(origins: Vec<Origin>, levels: u32) -> Vec<Origin> {
+    let mut origins = origins;
+    for _ in 0..levels {
+        origins = origins
+            .iter()
+            .flat_map(|o| (0..).zip(&CHILDREN).map(move |(i, c)| o.child(i, c)))
+            .collect();
+    }
+    origins
+}
+
+/// With `creases` set, neighbouring triangles whose normals have a dot
+/// product below it are kept sharp.
+pub fn loop_subdivide(triangles: Vec<Triangle>, levels: u32, creases: Option<f32>) -> Vec<Triangle> {
     let mut triangles = triangles;
     for _ in 0..levels {
         triangles = loop_subdivide_once(triangles, creases)
@@ -57,28 +84,42 @@ pub fn loop_subdivide(triangles: Vec<Triangle>, levels: u32, creases: bool) -> V
     triangles
 }
 
-pub fn loop_subdivide_once(triangles: Vec<Triangle>, creases: bool) -> Vec<Triangle> {
-    let map = get_vertex_map(&triangles);
-    let mut tris: Vec<Triangle> = Vec::new();
-    for triangle in &triangles[..] {
-        // even vertices
-        let a = triangle.vertices[0];
-        let b = triangle.vertices[1];
-        let c = triangle.vertices[2];
-
-        let ab = compute_odd(&a, &b, &c, get_furthest_vertex(&a, &b, &triangle, &map), creases);
-        let bc = compute_odd(&b, &c, &a, get_furthest_vertex(&b, &c, &triangle, &map), creases);
-        let ac = compute_odd(&c, &a, &b, get_furthest_vertex(&c, &a, &triangle, &map), creases);
-
-        let a = compute_even(&a, &map, creases);
-        let b = compute_even(&b, &map, creases);
-        let c = compute_even(&c, &map, creases);
+/// One Loop step. The triangles are welded into indexed form once, so that
+/// every edge and vertex point is worked out a single time and shared by
+/// the children that use it.
+pub fn loop_subdivide_once(triangles: Vec<Triangle>, creases: Option<f32>) -> Vec<Triangle> {
+    let (welded, faces) = weld(&triangles);
+    let positions: Vec<[f32; 3]> = welded.iter().map(|v| v.position).collect();
+    let topology = Topology::new(positions.len(), &faces);
+    let vertex = |v: usize| Vertex { position: positions[v], base: None };
+    // the corner of a triangle that is not on edge `e`
+    let opposite = |f: usize, e: usize| {
+        let [a, b] = topology.edges[e];
+        *faces[f].iter().find(|&&x| x != a && x != b).unwrap_or(&faces[f][2])
+    };
+
+    let edges: Vec<usize> = (0..topology.edges.len()).collect();
+    let odd = parallel::map(&edges, |&e| {
+        let [a, b] = topology.edges[e];
+        let f = topology.edge_faces[e][0];
+        let d = topology.across(e, f).map(|g| vertex(opposite(g, e)));
+        compute_odd(&vertex(a), &vertex(b), &vertex(opposite(f, e)), d.as_ref(), creases)
+    });
+    let vertices: Vec<usize> = (0..positions.len()).collect();
+    let even = parallel::map(&vertices, |&v| {
+        compute_even(&welded[v], v, &positions, &faces, &topology, creases)
+    });
+
+    let items: Vec<usize> = (0..faces.len()).collect();
+    parallel::flat_map(&items, |&t, tris| {
+        let [a, b, c] = [0, 1, 2].map(|i| even[faces[t][i]]);
+        let [ab, bc, ac] = [0, 1, 2].map(|i| odd[topology.face_edges[t][i]]);
 
         tris.push(Triangle {
             vertices: [a, ab, ac],
         });
         tris.push(Triangle {
-            vertices: [b, ab, bc],
+            vertices: [b, bc, ab],
         });
         tris.push(Triangle {
             vertices: [c, ac, bc],
@@ -86,15 +127,13 @@ pub fn loop_subdivide_once(triangles: Vec<Triangle>, creases: bool) -> Vec<Trian
         tris.push(Triangle {
             vertices: [ab, bc, ac],
         });
-    }
-
-    tris
+    })
 }
 
-fn compute_odd(a: &Vertex, b: &Vertex, c: &Vertex, o: Option<&Vertex>, creases: bool) -> Vertex {
+fn compute_odd(a: &Vertex, b: &Vertex, c: &Vertex, o: Option<&Vertex>, creases: Option<f32>) -> Vertex {
     if let Some(d) = o {
         let mut s = 0.0;
-        if creases {
+        if creases.is_some() {
             let shared_edge = vec3_normalized(vec3_sub(a.position, b.position));
             let e1 = vec3_normalized(vec3_sub(a.position, c.position));
             let e2 = vec3_normalized(vec3_sub(a.position, d.position));
@@ -102,12 +141,12 @@ fn compute_odd(a: &Vertex, b: &Vertex, c: &Vertex, o: Option<&Vertex>, creases:
             let n2 = vec3_normalized(vec3_cross(e2, shared_edge));
             s = vec3_dot(n1, n2);
         }
-        if creases && s < 0.6 {
+        if creases.is_some_and(|c| s < c) {
             let old_pos = vec3_add(
                 vec3_scale(a.position, 1.0 / 2.0),
                 vec3_scale(b.position, 1.0 / 2.0),
             );
-            Vertex { position: old_pos }
+            Vertex { position: old_pos, base: None }
         } else {
             Vertex {
                 position: vec3_add(
@@ -120,6 +159,7 @@ fn compute_odd(a: &Vertex, b: &Vertex, c: &Vertex, o: Option<&Vertex>, creases:
                         vec3_scale(d.position, 1.0 / 8.0),
                     ),
                 ),
+                base: None,
             }
         }
     } else {
@@ -128,48 +168,50 @@ fn compute_odd(a: &Vertex, b: &Vertex, c: &Vertex, o: Option<&Vertex>, creases:
                 vec3_scale(a.position, 1.0 / 2.0),
                 vec3_scale(b.position, 1.0 / 2.0),
             ),
+            base: None,
         }
     }
 }
 
-fn compute_even(v: &Vertex, map: &HashMap<&Vertex, HashSet<&Triangle>>, creases: bool) -> Vertex {
-    let tris = map.get(v).unwrap();
-    let mut surrounding: HashSet<Vertex> = HashSet::new();
-    let mut surrounding2: HashSet<Vertex> = HashSet::new();
+fn compute_even(
+    vertex: &Vertex,
+    v: usize,
+    positions: &[[f32; 3]],
+    faces: &[Vec<usize>],
+    topology: &Topology,
+    creases: Option<f32>,
+) -> Vertex {
+    let mut surrounding: Vec<usize> = Vec::new();
     let mut norms = Vec::new();
-    for tri in tris {
-        if !surrounding.insert(tri.vertices[0]) {
-            surrounding2.insert(tri.vertices[0]);
-        }
-        if !surrounding.insert(tri.vertices[1]) {
-            surrounding2.insert(tri.vertices[1]);
-        }
-        if !surrounding.insert(tri.vertices[2]) {
-            surrounding2.insert(tri.vertices[2]);
+    for &t in &topology.vertex_faces[v] {
+        for &u in &faces[t] {
+            if u != v && !surrounding.contains(&u) {
+                surrounding.push(u);
+            }
         }
-        if creases {
-            let e1 = vec3_normalized(vec3_sub(tri.vertices[0].position, tri.vertices[1].position));
-            let e2 = vec3_normalized(vec3_sub(tri.vertices[1].position, tri.vertices[2].position));
+        if creases.is_some() {
+            let [p0, p1, p2] = [0, 1, 2].map(|i| positions[faces[t][i]]);
+            let e1 = vec3_normalized(vec3_sub(p0, p1));
+            let e2 = vec3_normalized(vec3_sub(p1, p2));
             let n = vec3_normalized(vec3_cross(e1,e2));
             norms.push(n);
         }
     }
-    surrounding.remove(v);
-    surrounding2.remove(v);
 
     let n = surrounding.len();
     let k = n as f32;
+    let position = positions[v];
 
     if n == 2 {
-        let mut p = vec3_scale(v.position, 3.0 / 4.0);
-        for vertex in &surrounding {
-            p = vec3_add(p, vec3_scale(vertex.position, 1.0 / 8.0));
+        let mut p = vec3_scale(position, 3.0 / 4.0);
+        for &u in &surrounding {
+            p = vec3_add(p, vec3_scale(positions[u], 1.0 / 8.0));
         }
-        return Vertex { position: p };
+        return Vertex { position: p, base: vertex.base };
     }
 
     let mut s = 1.0;
-    if creases {
+    if creases.is_some() {
         for i in 0..norms.len() {
             for j in 0..norms.len() {
                 if i != j {
@@ -183,16 +225,16 @@ fn compute_even(v: &Vertex, map: &HashMap<&Vertex, HashSet<&Triangle>>, creases:
     }
 
     let beta = if n == 3 { 3.0 / 16.0 } else { 3.0 / (8.0 * k) };
-    let mut p = vec3_scale(v.position, 1.0 - k * beta);
+    let mut p = vec3_scale(position, 1.0 - k * beta);
 
-    for vertex in surrounding {
-        p = vec3_add(p, vec3_scale(vertex.position, beta));
+    for &u in &surrounding {
+        p = vec3_add(p, vec3_scale(positions[u], beta));
     }
 
-    if creases && s < 0.6 {
-        Vertex { position: v.position }
+    if creases.is_some_and(|c| s < c) {
+        Vertex { position, base: vertex.base }
     }
     else {
-        Vertex { position: p }
+        Vertex { position: p, base: vertex.base }
     }
 }
@@ -1,46 +1,36 @@
-use crate::triangle_subdivide::{Triangle, Vertex};
-
-use std::fs::File;
-use std::io::prelude::*;
-
-pub fn load_wavefront(path: &str) -> Vec<Triangle> {
-    let mut file = File::open(path).expect("File not found");
-    let mut bytes: Vec<u8> = Vec::new();
-    file.read_to_end(&mut bytes).unwrap();
-
-    let mut data = ::std::io::BufReader::new(&bytes[..]);
-    let data = obj::ObjData::load_buf(&mut data).unwrap();
+use crate::mesh::{Mesh, Origin};
+use crate::triangle_subdivide::{Triangle, Vertex, WELD_TOLERANCE};
 
+/// Splits every face of the mesh into triangles. Polygons with more than
+/// three vertices are fanned out from their first vertex.
+pub fn triangulate(mesh: &Mesh) -> (Vec<Triangle>, Vec<Origin>) {
     let mut triangles = Vec::new();
-
-    for object in data.objects.iter() {
-        for polygon in object.groups.iter().flat_map(|g| g.polys.iter()) {
-            match polygon {
-                obj::SimplePolygon(indices) => {
-                    assert!(indices.len() == 3);
-                    let v1i = indices.get(0).unwrap();
-                    let v2i = indices.get(1).unwrap();
-                    let v3i = indices.get(2).unwrap();
-
-                    let v1 = Vertex {
-                        position: data.position[v1i.0],
-                    };
-                    let v2 = Vertex {
-                        position: data.position[v2i.0],
-                    };
-                    let v3 = Vertex {
-                        position: data.position[v3i.0],
-                    };
-
-                    let triangle = Triangle {
-                        vertices: [v1, v2, v3],
-                    };
-
-                    triangles.push(triangle);
-                }
-            }
+    let mut origins = Vec::new();
+    let apart = mesh.kept_apart(WELD_TOLERANCE);
+
+    for (f, face) in mesh.faces.iter().enumerate() {
+        let v1 = Vertex {
+            position: mesh.positions[face[0]],
+            base: apart[face[0]],
+        };
+        for i in 1..face.len() - 1 {
+            let v2 = Vertex {
+                position: mesh.positions[face[i]],
+                base: apart[face[i]],
+            };
+            let v3 = Vertex {
+                position: mesh.positions[face[i + 1]],
+                base: apart[face[i + 1]],
+            };
+
+            let triangle = Triangle {
+                vertices: [v1, v2, v3],
+            };
+
+            triangles.push(triangle);
+            origins.push(Origin::of_corners(f, i - 1, face.len(), &[0, i, i + 1]));
         }
     }
 
-    triangles
+    (triangles, origins)
 }
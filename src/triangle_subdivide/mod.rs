@@ -2,144 +2,156 @@ mod divider;
 mod loader;
 mod transform;
 
-use crate::render::Vertex as RVertex;
-use std::collections::{HashMap, HashSet};
-use std::hash::{Hash, Hasher};
+use crate::frame::Frame;
+use crate::mesh::{self, Mesh, Origin};
+use crate::render::Coloring;
+use std::collections::HashMap;
 use vecmath::*;
 
-#[derive(Debug, Hash)]
+#[derive(Debug, Clone)]
 pub struct Triangle {
     vertices: [Vertex; 3],
 }
 
-impl Eq for Triangle {}
-
-impl PartialEq for Triangle {
-    fn eq(&self, other: &Self) -> bool {
-        self.vertices == other.vertices
-    }
-}
-
 #[derive(Debug, Clone, Copy)]
 pub struct Vertex {
     position: Vector3<f32>,
+    /// The base mesh vertex this is, or the vertex point of, where it must
+    /// not be welded to others at its position. See `Mesh::kept_apart`.
+    base: Option<usize>,
 }
 
-impl Eq for Vertex {}
+/// Corners closer than this are the same vertex when the triangles are welded.
+const WELD_TOLERANCE: f32 = 1e-4;
 
-impl PartialEq for Vertex {
-    fn eq(&self, other: &Self) -> bool {
-        (self.position[0] - other.position[0]).abs() < 1e-4
-            && (self.position[1] - other.position[1]).abs() < 1e-4
-            && (self.position[2] - other.position[2]).abs() < 1e-4
-    }
+/// Welds the triangles back into indexed positions and faces, numbering the
+/// vertices in the order the faces first use them.
+pub fn get_indexed(triangles: &[Triangle]) -> (Vec<[f32; 3]>, Vec<Vec<usize>>) {
+    let (vertices, faces) = weld(triangles);
+    (vertices.iter().map(|v| v.position).collect(), faces)
 }
 
-impl Hash for Vertex {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        (self.position[0] as i32).hash(state);
-        (self.position[1] as i32).hash(state);
-        (self.position[2] as i32).hash(state);
-    }
+/// `get_indexed`, keeping the base vertex of every welded vertex.
+fn weld(triangles: &[Triangle]) -> (Vec<Vertex>, Vec<Vec<usize>>) {
+    let corners: Vec<Vertex> = triangles.iter().flat_map(|t| t.vertices).collect();
+    let positions: Vec<[f32; 3]> = corners.iter().map(|v| v.position).collect();
+    let bases: Vec<Option<usize>> = corners.iter().map(|v| v.base).collect();
+    let (remap, kept) = mesh::weld_apart(&positions, &bases, WELD_TOLERANCE);
+    let faces = remap.chunks(3).map(<[usize]>::to_vec).collect();
+    (kept.iter().map(|&i| corners[i]).collect(), faces)
 }
 
-pub fn get_furthest_vertex<'a>(
-    a: &'a Vertex,
-    b: &'a Vertex,
-    me: &'a Triangle,
-    map: &'a HashMap<&'a Vertex, HashSet<&'a Triangle>>,
-) -> Option<&'a Vertex> {
-    map.get(a)
-        .unwrap()
-        .intersection(map.get(b).unwrap())
-        .find(|&&x| x != me)
-        .map(|t| {
-            if t.vertices[0] != *a && t.vertices[0] != *b {
-                return &t.vertices[0];
-            } else if t.vertices[1] != *a && t.vertices[1] != *b {
-                return &t.vertices[1];
-            } else {
-                return &t.vertices[2];
-            }
-        })
+/// The triangles of every level computed so far, so changing levels in the
+/// viewer only runs the subdivision steps it has not run before.
+#[derive(Default)]
+pub struct Levels {
+    /// Where each triangle of `linear[0]` lies in the base mesh.
+    origins: Vec<Origin>,
+    /// `linear[l]` is the base mesh after `l` linear levels.
+    linear: Vec<Vec<Triangle>>,
+    /// `smooth[&(l, creases)][s]` is `linear[l]` after `s + 1` Loop levels.
+    smooth: HashMap<(u32, Option<u32>), Vec<Vec<Triangle>>>,
 }
 
-/*
-pub fn get_adjacent<'a>(
-    a: &'a Vertex,
-    b: &'a Vertex,
-    me: &'a Triangle,
-    map: &'a HashMap<&'a Vertex, HashSet<&'a Triangle>>,
-) -> &'a Triangle {
-    *map.get(a)
-        .unwrap()
-        .intersection(map.get(b).unwrap())
-        .find(|&&x| x != me)
-        .unwrap()
-}
-*/
+impl Levels {
+    /// Checks `cancelled` between steps, giving up with `None` once it
+    /// returns true. The steps already run stay cached.
+    pub fn get_frame(
+        &mut self,
+        base: &Mesh,
+        linear_levels: u32,
+        loop_levels: u32,
+        creases: Option<f32>,
+        coloring: Coloring,
+        cancelled: &dyn Fn() -> bool,
+    ) -> Option<Frame> {
+        self.run(base, linear_levels, loop_levels, creases, cancelled)?;
+        let triangles = self.level(linear_levels, loop_levels, creases);
+        // every step pushes four children per triangle, in order
+        let base_faces = (coloring == Coloring::BaseFaces).then(|| {
+            let shift = 2 * (linear_levels + loop_levels);
+            (0..triangles.len())
+                .map(|i| self.origins[i >> shift].face)
+                .collect::<Vec<usize>>()
+        });
+        Some(transform::transform(triangles.to_vec(), base_faces.as_deref()))
+    }
 
-pub fn get_vertex_map(triangles: &Vec<Triangle>) -> HashMap<&Vertex, HashSet<&Triangle>> {
-    let mut vertices: HashMap<&Vertex, HashSet<&Triangle>> = HashMap::new();
+    /// The level as `subdivide` would return it, from the cached steps and
+    /// giving up like `get_frame` does.
+    pub fn get_mesh(
+        &mut self,
+        base: &Mesh,
+        linear_levels: u32,
+        loop_levels: u32,
+        creases: Option<f32>,
+        cancelled: &dyn Fn() -> bool,
+    ) -> Option<Mesh> {
+        self.run(base, linear_levels, loop_levels, creases, cancelled)?;
+        let (positions, faces) = get_indexed(self.level(linear_levels, loop_levels, creases));
+        let origins = divider::subdivide_origins(self.origins.clone(), linear_levels + loop_levels);
+        Some(Mesh::refined(base, positions, faces, &origins))
+    }
 
-    for triangle in triangles {
-        for v in &triangle.vertices {
-            if !vertices.contains_key(v) {
-                vertices.insert(&v, HashSet::new());
+    /// Runs the steps up to the given level that are not cached yet.
+    fn run(
+        &mut self,
+        base: &Mesh,
+        linear_levels: u32,
+        loop_levels: u32,
+        creases: Option<f32>,
+        cancelled: &dyn Fn() -> bool,
+    ) -> Option<()> {
+        if self.linear.is_empty() {
+            let (triangles, origins) = loader::triangulate(base);
+            self.origins = origins;
+            self.linear.push(triangles);
+        }
+        while self.linear.len() <= linear_levels as usize {
+            if cancelled() {
+                return None;
             }
-            vertices.get_mut(&v).unwrap().insert(triangle);
+            let next = divider::linear_subdivide_once(self.linear.last().unwrap().clone());
+            self.linear.push(next);
         }
-    }
-
-    vertices
-}
-
-pub fn get_adjacency(triangles: &Vec<Triangle>) -> HashMap<&Triangle, [Option<&Triangle>; 3]> {
-    let mut map = HashMap::new();
-
-    let mut vertices: HashMap<&Vertex, HashSet<&Triangle>> = HashMap::new();
 
-    for triangle in triangles {
-        for v in &triangle.vertices {
-            if !vertices.contains_key(v) {
-                vertices.insert(&v, HashSet::new());
+        let linear = &self.linear[linear_levels as usize];
+        let smooth = self
+            .smooth
+            .entry((linear_levels, creases.map(f32::to_bits)))
+            .or_default();
+        while smooth.len() < loop_levels as usize {
+            if cancelled() {
+                return None;
             }
-            vertices.get_mut(&v).unwrap().insert(triangle);
+            let next = divider::loop_subdivide_once(smooth.last().unwrap_or(linear).clone(), creases);
+            smooth.push(next);
         }
+        Some(())
     }
 
-    for triangle in triangles {
-        let v1 = triangle.vertices[0];
-        let v2 = triangle.vertices[1];
-        let v3 = triangle.vertices[2];
-        let n1 = vertices
-            .get(&v1)
-            .unwrap()
-            .intersection(vertices.get(&v2).unwrap())
-            .find(|&&x| x != triangle)
-            .map(|x| *x);
-        let n2 = vertices
-            .get(&v2)
-            .unwrap()
-            .intersection(vertices.get(&v3).unwrap())
-            .find(|&&x| x != triangle)
-            .map(|x| *x);
-
-        let n3 = vertices
-            .get(&v3)
-            .unwrap()
-            .intersection(vertices.get(&v1).unwrap())
-            .find(|&&x| x != triangle)
-            .map(|x| *x);
-        map.insert(triangle, [n1, n2, n3]);
+    /// A level `run` has computed.
+    fn level(&self, linear_levels: u32, loop_levels: u32, creases: Option<f32>) -> &[Triangle] {
+        match loop_levels {
+            0 => &self.linear[linear_levels as usize],
+            n => &self.smooth[&(linear_levels, creases.map(f32::to_bits))][n as usize - 1],
+        }
     }
-
-    map
 }
 
-pub fn get_vertices(path: &str, linear_levels: u32, loop_levels: u32, creases: bool) -> Vec<RVertex> {
-    let mut triangles = loader::load_wavefront(path);
+/// Runs the same pipeline as `Levels::get_frame` but returns an indexed mesh in the
+/// original coordinate frame.
+pub fn subdivide(base: &Mesh, linear_levels: u32, loop_levels: u32, creases: Option<f32>) -> Mesh {
+    let (mut triangles, origins) = loader::triangulate(base);
     triangles = divider::linear_subdivide(triangles, linear_levels);
     triangles = divider::loop_subdivide(triangles, loop_levels, creases);
-    transform::transform(triangles)
+    let origins = divider::subdivide_origins(origins, linear_levels + loop_levels);
+    let (positions, faces) = get_indexed(&triangles);
+    Mesh::refined(base, positions, faces, &origins)
+}
+
+/// Which base face, and where in it, each face of `subdivide`'s output comes
+/// from.
+pub fn origins(base: &Mesh, levels: u32) -> Vec<Origin> {
+    divider::subdivide_origins(loader::triangulate(base).1, levels)
 }
@@ -1,11 +1,14 @@
-use crate::render::Vertex;
-use crate::triangle_subdivide::{get_adjacency, Triangle};
-use std::collections::HashMap;
-use vecmath::*;
-
-pub fn transform(triangles: Vec<Triangle>) -> Vec<Vertex> {
-    let map = get_adjacency(&triangles);
-    let mut color_map: HashMap<&Triangle, usize> = HashMap::new();
+use crate::frame::Frame;
+use crate::render::base_face_color;
+use crate::topology::Topology;
+use crate::triangle_subdivide::{get_indexed, Triangle};
+
+/// Colours the triangles so that neighbours differ, or with `base_faces` by
+/// the base face each one lies in, and welds them into a frame to draw.
+pub fn transform(triangles: Vec<Triangle>, base_faces: Option<&[usize]>) -> Frame {
+    let (positions, faces) = get_indexed(&triangles);
+    let topology = Topology::new(positions.len(), &faces);
+    let mut color_map = vec![usize::MAX; faces.len()];
     let colors = [
         [1.0, 0.0, 0.0],
         [0.0, 1.0, 0.0],
@@ -13,54 +16,25 @@ pub fn transform(triangles: Vec<Triangle>) -> Vec<Vertex> {
         [1.0, 1.0, 0.0],
     ];
 
-    let mut av = [0.0, 0.0, 0.0];
-    let mut r: Vec<Vertex> = Vec::new();
-    for tri in &triangles[..] {
+    let mut face_colors = Vec::with_capacity(faces.len());
+    for (i, edges) in topology.face_edges.iter().enumerate() {
         let mut taken = [false, false, false, false];
-        for triangle in map.get(tri).unwrap() {
-            if let Some(t) = triangle {
-                if color_map.contains_key(t) {
-                    taken[*color_map.get(t).unwrap()] = true;
-                }
+        for g in edges.iter().filter_map(|&e| topology.across(e, i)) {
+            if color_map[g] != usize::MAX {
+                taken[color_map[g]] = true;
             }
         }
 
-        let mut c = 5;
-        for i in 0..4 {
-            if !taken[i] {
-                c = i;
-                break;
-            }
-        }
+        let c = taken.iter().position(|&t| !t).unwrap_or(5);
 
-        color_map.insert(tri, c);
-
-        let render_vertex1 = Vertex {
-            position: tri.vertices[0].position,
-            color: colors[c],
-        };
-        let render_vertex2 = Vertex {
-            position: tri.vertices[1].position,
-            color: colors[c],
+        color_map[i] = c;
+        let color = match base_faces {
+            Some(faces) => base_face_color(faces[i]),
+            None => colors[c],
         };
-        let render_vertex3 = Vertex {
-            position: tri.vertices[2].position,
-            color: colors[c],
-        };
-        r.push(render_vertex1);
-        r.push(render_vertex2);
-        r.push(render_vertex3);
-
-        for i in 0..3 {
-            av = vec3_add(av, tri.vertices[i].position);
-        }
-    }
-
-    av = vec3_scale(av, 1.0 / (3.0 * triangles.len() as f32));
 
-    for i in 0..r.len() {
-        r[i].position = vec3_sub(r[i].position, av);
+        face_colors.push(color);
     }
 
-    r
+    Frame::with_face_colors(&positions, &faces, &face_colors)
 }
@@ -0,0 +1,329 @@
+use crate::mesh::{face_normal, Mesh};
+
+use std::collections::HashMap;
+use std::fmt;
+use vecmath::*;
+
+/// Something wrong with a mesh that the subdividers do not handle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    FaceTooSmall {
+        face: usize,
+        len: usize,
+    },
+    MissingVertex {
+        face: usize,
+        vertex: usize,
+    },
+    RepeatedVertex {
+        face: usize,
+        vertex: usize,
+    },
+    /// An edge shared by more than two faces.
+    NonManifoldEdge {
+        edge: [usize; 2],
+        faces: Vec<usize>,
+    },
+    /// A vertex whose faces form more than one fan, like two cones touching
+    /// at their tips.
+    NonManifoldVertex {
+        vertex: usize,
+        fans: usize,
+    },
+    /// Two faces that run along their shared edge in the same direction, so
+    /// one of them is wound the wrong way.
+    FlippedFaces {
+        edge: [usize; 2],
+        faces: [usize; 2],
+    },
+    DuplicateFace {
+        face: usize,
+        of: usize,
+    },
+    ZeroAreaFace {
+        face: usize,
+    },
+    IsolatedVertex {
+        vertex: usize,
+    },
+}
+
+impl Problem {
+    /// Errors make the subdividers produce garbage, the rest only affect
+    /// parts of the result.
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            Problem::FaceTooSmall { .. }
+                | Problem::MissingVertex { .. }
+                | Problem::NonManifoldEdge { .. }
+                | Problem::NonManifoldVertex { .. }
+        )
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::FaceTooSmall { face, len } => {
+                write!(f, "face {} has only {} vertices", face, len)
+            }
+            Problem::MissingVertex { face, vertex } => {
+                write!(f, "face {} uses missing vertex {}", face, vertex)
+            }
+            Problem::RepeatedVertex { face, vertex } => {
+                write!(f, "face {} uses vertex {} more than once", face, vertex)
+            }
+            Problem::NonManifoldEdge { edge, faces } => write!(
+                f,
+                "edge {}-{} is shared by {} faces {:?}",
+                edge[0],
+                edge[1],
+                faces.len(),
+                faces
+            ),
+            Problem::NonManifoldVertex { vertex, fans } => {
+                write!(f, "vertex {} joins {} separate fans of faces", vertex, fans)
+            }
+            Problem::FlippedFaces { edge, faces } => write!(
+                f,
+                "faces {} and {} disagree on orientation along edge {}-{}",
+                faces[0], faces[1], edge[0], edge[1]
+            ),
+            Problem::DuplicateFace { face, of } => {
+                write!(f, "face {} duplicates face {}", face, of)
+            }
+            Problem::ZeroAreaFace { face } => write!(f, "face {} has zero area", face),
+            Problem::IsolatedVertex { vertex } => {
+                write!(f, "vertex {} is not used by any face", vertex)
+            }
+        }
+    }
+}
+
+fn find(parent: &mut [usize], x: usize) -> usize {
+    let mut x = x;
+    while parent[x] != x {
+        parent[x] = parent[parent[x]];
+        x = parent[x];
+    }
+    x
+}
+
+/// Splits `faces`, the faces around `vertex`, into fans of faces joined by
+/// edges through the vertex. A manifold vertex has a single fan.
+pub fn fans(mesh: &Mesh, vertex: usize, faces: &[usize]) -> Vec<Vec<usize>> {
+    let mut parent: Vec<usize> = (0..faces.len()).collect();
+    let mut by_neighbor: HashMap<usize, usize> = HashMap::new();
+    for (i, &f) in faces.iter().enumerate() {
+        let face = &mesh.faces[f];
+        let at = face.iter().position(|&x| x == vertex).unwrap();
+        let neighbors = [
+            face[(at + 1) % face.len()],
+            face[(at + face.len() - 1) % face.len()],
+        ];
+        for n in neighbors {
+            if let Some(&j) = by_neighbor.get(&n) {
+                let a = find(&mut parent, i);
+                let b = find(&mut parent, j);
+                parent[a] = b;
+            } else {
+                by_neighbor.insert(n, i);
+            }
+        }
+    }
+
+    let mut fans: Vec<Vec<usize>> = Vec::new();
+    let mut fan_of_root = HashMap::new();
+    for (i, &f) in faces.iter().enumerate() {
+        let root = find(&mut parent, i);
+        let fan = *fan_of_root.entry(root).or_insert_with(|| {
+            fans.push(Vec::new());
+            fans.len() - 1
+        });
+        fans[fan].push(f);
+    }
+    fans
+}
+
+/// Whether the face is too thin to have an orientation: twice its area,
+/// the length of `face_normal`, is tiny next to its own squared edge
+/// lengths, so the result is the same wherever the face is and whatever
+/// the size of the rest of the mesh.
+pub fn has_zero_area(positions: &[[f32; 3]], face: &[usize]) -> bool {
+    let edges: f32 = (0..face.len())
+        .map(|i| {
+            let e = vec3_sub(positions[face[(i + 1) % face.len()]], positions[face[i]]);
+            vec3_dot(e, e)
+        })
+        .sum();
+    vec3_len(face_normal(positions, face)) <= 1e-6 * edges
+}
+
+/// Checks the mesh and lists every problem found, errors first.
+pub fn validate(mesh: &Mesh) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    let mut good = vec![true; mesh.faces.len()];
+    for (f, face) in mesh.faces.iter().enumerate() {
+        if face.len() < 3 {
+            problems.push(Problem::FaceTooSmall {
+                face: f,
+                len: face.len(),
+            });
+            good[f] = false;
+        }
+        if let Some(&vertex) = face.iter().find(|&&v| v >= mesh.positions.len()) {
+            problems.push(Problem::MissingVertex { face: f, vertex });
+            good[f] = false;
+        }
+        for (i, &v) in face.iter().enumerate() {
+            if face[..i].contains(&v) {
+                problems.push(Problem::RepeatedVertex { face: f, vertex: v });
+                good[f] = false;
+                break;
+            }
+        }
+    }
+
+    // directed half edges, keyed by the undirected edge
+    let mut edges: HashMap<[usize; 2], Vec<(usize, bool)>> = HashMap::new();
+    let mut vertex_faces = vec![Vec::new(); mesh.positions.len()];
+    for (f, face) in mesh.faces.iter().enumerate().filter(|(f, _)| good[*f]) {
+        for i in 0..face.len() {
+            let a = face[i];
+            let b = face[(i + 1) % face.len()];
+            edges
+                .entry([a.min(b), a.max(b)])
+                .or_default()
+                .push((f, a < b));
+            vertex_faces[a].push(f);
+        }
+    }
+
+    let mut sorted: Vec<_> = edges.iter().collect();
+    sorted.sort_unstable_by_key(|(edge, _)| **edge);
+    let mut flipped = Vec::new();
+    for (&edge, half_edges) in sorted {
+        if half_edges.len() > 2 {
+            problems.push(Problem::NonManifoldEdge {
+                edge,
+                faces: half_edges.iter().map(|h| h.0).collect(),
+            });
+        } else if half_edges.len() == 2 && half_edges[0].1 == half_edges[1].1 {
+            flipped.push(Problem::FlippedFaces {
+                edge,
+                faces: [half_edges[0].0, half_edges[1].0],
+            });
+        }
+    }
+
+    for (v, faces) in vertex_faces.iter().enumerate() {
+        if faces.is_empty() {
+            continue;
+        }
+        let fans = fans(mesh, v, faces);
+        if fans.len() > 1 {
+            problems.push(Problem::NonManifoldVertex {
+                vertex: v,
+                fans: fans.len(),
+            });
+        }
+    }
+
+    problems.extend(flipped);
+
+    let mut seen: HashMap<Vec<usize>, usize> = HashMap::new();
+    for (f, face) in mesh.faces.iter().enumerate().filter(|(f, _)| good[*f]) {
+        let mut key = face.clone();
+        key.sort_unstable();
+        match seen.get(&key) {
+            Some(&of) => problems.push(Problem::DuplicateFace { face: f, of }),
+            None => {
+                seen.insert(key, f);
+            }
+        }
+    }
+
+    for (f, face) in mesh.faces.iter().enumerate().filter(|(f, _)| good[*f]) {
+        if has_zero_area(&mesh.positions, face) {
+            problems.push(Problem::ZeroAreaFace { face: f });
+        }
+    }
+
+    for (v, faces) in vertex_faces.iter().enumerate() {
+        if faces.is_empty() {
+            problems.push(Problem::IsolatedVertex { vertex: v });
+        }
+    }
+
+    problems.sort_by_key(|p| !p.is_error());
+    problems
+}
+
+/// Validates the mesh before subdividing it: warnings are printed, errors are
+/// printed and refused unless `force` is set.
+pub fn check(mesh: &Mesh, force: bool) -> Result<(), String> {
+    let problems = validate(mesh);
+    for problem in &problems {
+        let kind = if problem.is_error() {
+            "error"
+        } else {
+            "warning"
+        };
+        eprintln!("{}: {}", kind, problem);
+    }
+
+    let errors = problems.iter().filter(|p| p.is_error()).count();
+    if errors > 0 && !force {
+        Err(format!(
+            "the mesh has {} errors that break subdivision, use --force to subdivide anyway",
+            errors
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A closed tetrahedron with edges about `size` long, moved to `offset`.
+    fn tetrahedron(offset: [f32; 3], size: f32) -> Mesh {
+        let corners = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+        ];
+        Mesh {
+            positions: corners
+                .iter()
+                .map(|&c| vec3_add(offset, vec3_scale(c, size)))
+                .collect(),
+            faces: vec![vec![0, 2, 1], vec![0, 1, 3], vec![0, 3, 2], vec![1, 2, 3]],
+            ..Mesh::default()
+        }
+    }
+
+    #[test]
+    fn small_faces_far_from_the_origin_have_area() {
+        let mesh = tetrahedron([1e4, 2e4, 3e3], 0.1);
+        assert_eq!(validate(&mesh), Vec::new());
+    }
+
+    #[test]
+    fn collinear_faces_have_zero_area() {
+        let positions = [
+            [1e4, 2e4, 3e3],
+            [1e4 + 1.0, 2e4 + 1.0, 3e3],
+            [1e4 + 2.0, 2e4 + 2.0, 3e3],
+        ];
+        assert!(has_zero_area(&positions, &[0, 1, 2]));
+        assert!(!has_zero_area(
+            &[[0.0; 3], [1.0, 0.0, 0.0], [0.0, 1e-3, 0.0]],
+            &[0, 1, 2]
+        ));
+    }
+}
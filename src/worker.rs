@@ -0,0 +1,265 @@
+use crate::frame::Frame;
+use crate::mesh::Mesh;
+use crate::render::{self, Coloring};
+use crate::scheme::{Cache, Scheme};
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+enum Job {
+    Levels {
+        generation: u64,
+        scheme: Scheme,
+        linear_levels: u32,
+        levels: u32,
+        creases: Option<f32>,
+        coloring: Coloring,
+    },
+    Reload(Mesh),
+    Export {
+        export: Export,
+        path: String,
+        scheme: Scheme,
+        linear_levels: u32,
+        levels: u32,
+        creases: Option<f32>,
+    },
+}
+
+/// What the viewer's export hotkeys write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Export {
+    /// The mesh on screen, with E.
+    Mesh,
+    /// Every level up to the one on screen, with L.
+    Levels,
+    /// The control cage after the linear levels, with U.
+    Cage,
+}
+
+/// Runs the viewer's subdivision on its own thread so the window keeps
+/// drawing. Each request gets a new generation; the worker gives up on a job
+/// as soon as a newer one is requested, and only the newest result is
+/// handed back.
+pub struct Worker {
+    jobs: Sender<Job>,
+    results: Receiver<(u64, Frame)>,
+    generation: Arc<AtomicU64>,
+    pending: bool,
+}
+
+impl Worker {
+    pub fn spawn(cache: Cache) -> Worker {
+        let (jobs, job_receiver) = mpsc::channel();
+        let (result_sender, results) = mpsc::channel();
+        let generation = Arc::new(AtomicU64::new(0));
+        let latest = Arc::clone(&generation);
+        thread::spawn(move || run(cache, job_receiver, result_sender, latest));
+        Worker {
+            jobs,
+            results,
+            generation,
+            pending: false,
+        }
+    }
+
+    /// Starts subdividing to the given levels, cancelling any earlier
+    /// request.
+    pub fn request(
+        &mut self,
+        scheme: Scheme,
+        linear_levels: u32,
+        levels: u32,
+        creases: Option<f32>,
+        coloring: Coloring,
+    ) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.pending = true;
+        self.send(Job::Levels {
+            generation,
+            scheme,
+            linear_levels,
+            levels,
+            creases,
+            coloring,
+        });
+    }
+
+    /// Replaces the base mesh and drops every cached level, keeping the
+    /// textures, which must cover its Ptex faces. Takes effect for the
+    /// requests made after it.
+    pub fn reload(&mut self, base: Mesh) {
+        self.send(Job::Reload(base));
+    }
+
+    /// Writes an export next to `path` from the cached levels, after the
+    /// jobs already requested. Exports are never cancelled.
+    pub fn export(
+        &mut self,
+        export: Export,
+        path: &str,
+        scheme: Scheme,
+        linear_levels: u32,
+        levels: u32,
+        creases: Option<f32>,
+    ) {
+        self.send(Job::Export {
+            export,
+            path: path.to_string(),
+            scheme,
+            linear_levels,
+            levels,
+            creases,
+        });
+    }
+
+    /// Whether a requested result has not arrived yet.
+    pub fn is_busy(&self) -> bool {
+        self.pending
+    }
+
+    /// The result of the newest request, once it is ready.
+    pub fn poll(&mut self) -> Option<Frame> {
+        let mut newest = None;
+        loop {
+            match self.results.try_recv() {
+                Ok((generation, frame)) => {
+                    if generation == self.generation.load(Ordering::SeqCst) {
+                        self.pending = false;
+                        newest = Some(frame);
+                    }
+                }
+                Err(TryRecvError::Empty) => return newest,
+                Err(TryRecvError::Disconnected) => panic!("the subdivision thread stopped"),
+            }
+        }
+    }
+
+    fn send(&self, job: Job) {
+        self.jobs.send(job).expect("the subdivision thread stopped");
+    }
+}
+
+fn run(
+    mut cache: Cache,
+    jobs: Receiver<Job>,
+    results: Sender<(u64, Frame)>,
+    latest: Arc<AtomicU64>,
+) {
+    for job in jobs {
+        match job {
+            Job::Reload(base) => cache = Cache::new(base, cache.textures.take()),
+            Job::Levels {
+                generation,
+                scheme,
+                linear_levels,
+                levels,
+                creases,
+                coloring,
+            } => {
+                let cancelled = || latest.load(Ordering::SeqCst) != generation;
+                if let Some(frame) =
+                    cache.get_frame(scheme, linear_levels, levels, creases, coloring, &cancelled)
+                {
+                    if results.send((generation, frame.clone())).is_err() {
+                        return;
+                    }
+                }
+            }
+            Job::Export {
+                export,
+                path,
+                scheme,
+                linear_levels,
+                levels,
+                creases,
+            } => match export {
+                Export::Mesh => {
+                    render::export(&mut cache, &path, scheme, linear_levels, levels, creases)
+                }
+                Export::Levels => {
+                    render::export_levels(&mut cache, &path, scheme, linear_levels, levels, creases)
+                }
+                Export::Cage => {
+                    render::export_cage(&mut cache, &path, scheme, linear_levels, creases)
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh;
+
+    fn levels(generation: u64, levels: u32) -> Job {
+        Job::Levels {
+            generation,
+            scheme: Scheme::CatmullClark,
+            linear_levels: 0,
+            levels,
+            creases: None,
+            coloring: Coloring::Faces,
+        }
+    }
+
+    #[test]
+    fn newer_requests_cancel_older_ones() {
+        let cache = Cache::new(mesh::load("assets/quad_cube.obj").unwrap(), None);
+        let (jobs, job_receiver) = mpsc::channel();
+        let (result_sender, results) = mpsc::channel();
+        // generation 2 was requested before the worker got to 1, which would
+        // take far too long to finish
+        jobs.send(levels(1, 8)).unwrap();
+        jobs.send(levels(2, 1)).unwrap();
+        drop(jobs);
+        run(
+            cache,
+            job_receiver,
+            result_sender,
+            Arc::new(AtomicU64::new(2)),
+        );
+
+        let results: Vec<(u64, Frame)> = results.iter().collect();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 2);
+    }
+
+    #[test]
+    fn exports_the_level_on_screen() {
+        let path = &mesh::temp_path("worker_export.obj");
+        let base = mesh::load("assets/quad_cube.obj").unwrap();
+        mesh::save(&base, path).unwrap();
+        let (jobs, job_receiver) = mpsc::channel();
+        let (result_sender, _results) = mpsc::channel();
+        jobs.send(levels(1, 2)).unwrap();
+        jobs.send(Job::Export {
+            export: Export::Mesh,
+            path: path.to_string(),
+            scheme: Scheme::CatmullClark,
+            linear_levels: 0,
+            levels: 2,
+            creases: None,
+        })
+        .unwrap();
+        drop(jobs);
+        let cache = Cache::new(base.clone(), None);
+        run(
+            cache,
+            job_receiver,
+            result_sender,
+            Arc::new(AtomicU64::new(1)),
+        );
+
+        let (stem, _) = path.rsplit_once('.').unwrap();
+        let written = mesh::load(&format!("{}_subdivided.obj", stem)).unwrap();
+        let expected = crate::scheme::subdivide(Scheme::CatmullClark, &base, 0, 2, None);
+        assert_eq!(written.positions.len(), expected.positions.len());
+        assert_eq!(written.faces, expected.faces);
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(format!("{}_subdivided.obj", stem)).unwrap();
+    }
+}